@@ -0,0 +1,289 @@
+use bson::Bson;
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use mongodb::db::{Database, ThreadedDatabase};
+use regex::{Regex, RegexBuilder};
+
+use analyzer::{self, Analyzer};
+use dump;
+use error::TipupError;
+use flag_manager::Flag;
+use result_window;
+
+//matches http responses against a library of known blockpage fingerprints
+pub struct BlockpageAnalyzer {
+    name: String,
+    status: String,
+    body_field: Vec<String>,
+    headers_field: Vec<String>,
+    content_length_field: Vec<String>,
+    fingerprints: Vec<Fingerprint>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct BlockpageParameters {
+    pub body_field: Option<Vec<String>>,
+    pub headers_field: Option<Vec<String>>,
+    pub content_length_field: Option<Vec<String>>,
+    pub fingerprint_file: Option<String>,
+}
+
+struct Fingerprint {
+    name: String,
+    body_sha256: Vec<String>,
+    title: Option<Regex>,
+    headers: Vec<(String, Regex)>,
+    content_length: Option<(i64, i64)>,
+}
+
+impl Fingerprint {
+    fn parse(document: &OrderedDocument) -> Result<Fingerprint, TipupError> {
+        let name = match document.get("name") {
+            Some(&Bson::String(ref name)) => name.to_owned(),
+            _ => return Err(TipupError::from("failed to parse fingerprint name")),
+        };
+
+        let body_sha256 = match try!(analyzer::parse_string_array(document, "body_sha256", &name)) {
+            Some(body_sha256) => body_sha256.iter().map(|x| x.to_lowercase()).collect(),
+            None => Vec::new(),
+        };
+
+        let title = match document.get("title") {
+            Some(&Bson::String(ref title)) => Some(try!(compile_regex(title, &name))),
+            None => None,
+            _ => return Err(TipupError::from(format!("failed to parse 'title' as String in fingerprint '{}'", name))),
+        };
+
+        let mut headers = Vec::new();
+        match document.get("headers") {
+            Some(&Bson::Document(ref header_document)) => {
+                for (header, pattern) in header_document.iter() {
+                    match *pattern {
+                        Bson::String(ref pattern) => headers.push((header.to_lowercase(), try!(compile_regex(pattern, &name)))),
+                        _ => return Err(TipupError::from(format!("failed to parse header '{}' pattern as String in fingerprint '{}'", header, name))),
+                    }
+                }
+            },
+            None => (),
+            _ => return Err(TipupError::from(format!("failed to parse 'headers' as a document in fingerprint '{}'", name))),
+        }
+
+        let content_length = match document.get("content_length") {
+            Some(&Bson::Array(ref range)) if range.len() == 2 => match (as_i64(&range[0]), as_i64(&range[1])) {
+                (Some(minimum), Some(maximum)) => Some((minimum, maximum)),
+                _ => return Err(TipupError::from(format!("failed to parse 'content_length' range in fingerprint '{}'", name))),
+            },
+            Some(value) => match as_i64(value) {
+                Some(length) => Some((length, length)),
+                None => return Err(TipupError::from(format!("failed to parse 'content_length' in fingerprint '{}'", name))),
+            },
+            None => None,
+        };
+
+        if body_sha256.len() == 0 && title.is_none() && headers.len() == 0 && content_length.is_none() {
+            return Err(TipupError::from(format!("fingerprint '{}' has no criteria", name)));
+        }
+
+        Ok(
+            Fingerprint {
+                name: name,
+                body_sha256: body_sha256,
+                title: title,
+                headers: headers,
+                content_length: content_length,
+            }
+        )
+    }
+}
+
+impl BlockpageAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &BlockpageParameters, db: Option<&Database>, flag_tx: Sender<Flag>) -> Result<BlockpageAnalyzer, TipupError> {
+        //parse parameters
+        let body_field = try!(analyzer::parse_path(&parameters.body_field, "body_field", &["body"], name));
+        let headers_field = try!(analyzer::parse_path(&parameters.headers_field, "headers_field", &["headers"], name));
+        let content_length_field = try!(analyzer::parse_path(&parameters.content_length_field, "content_length_field", &["content_length"], name));
+
+        //load fingerprint library
+        let documents = match parameters.fingerprint_file {
+            Some(ref filename) => try!(dump::read_json_documents(filename, "fingerprint file")),
+            None => {
+                let db = match db {
+                    Some(db) => db,
+                    None => return Err(TipupError::from(format!("'fingerprint_file' parameter is required without mongodb in analyzer '{}'", name))),
+                };
+
+                let mut documents = Vec::new();
+                let cursor = try!(db.collection("fingerprints").find(None, None));
+                for document in cursor {
+                    documents.push(try!(document));
+                }
+
+                documents
+            },
+        };
+
+        let mut fingerprints = Vec::new();
+        for document in documents.iter() {
+            match Fingerprint::parse(document) {
+                Ok(fingerprint) => fingerprints.push(fingerprint),
+                Err(e) => return Err(TipupError::from(format!("failed to load fingerprint in analyzer '{}': {}", name, e))),
+            }
+        }
+
+        info!("loaded {} fingerprint(s) for analyzer '{}'", fingerprints.len(), name);
+        Ok(
+            BlockpageAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                body_field: body_field,
+                headers_field: headers_field,
+                content_length_field: content_length_field,
+                fingerprints: fingerprints,
+                flag_tx: flag_tx,
+            }
+        )
+    }
+}
+
+impl Analyzer for BlockpageAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve response from document
+        let body = match result_window::get_field(&self.body_field, document) {
+            Some(&Bson::String(ref body)) => Some(body.as_str()),
+            _ => None,
+        };
+
+        let headers = match result_window::get_field(&self.headers_field, document) {
+            Some(headers) => parse_headers(headers),
+            None => Vec::new(),
+        };
+
+        let content_length = match result_window::get_field(&self.content_length_field, document) {
+            Some(value) => as_i64(value),
+            None => body.map(|x| x.len() as i64),
+        };
+
+        if body.is_none() && headers.len() == 0 && content_length.is_none() {
+            return Ok(());
+        }
+
+        //hash and title are computed once for all fingerprints
+        let body_sha256 = body.map(|x| {
+            let mut hasher = Sha256::new();
+            hasher.input_str(x);
+            hasher.result_str()
+        });
+
+        let title = body.and_then(parse_title);
+
+        for fingerprint in self.fingerprints.iter() {
+            let mut matched = Vec::new();
+            if fingerprint.body_sha256.len() > 0 {
+                match body_sha256 {
+                    Some(ref body_sha256) if fingerprint.body_sha256.contains(body_sha256) => matched.push("body_sha256"),
+                    _ => continue,
+                }
+            }
+
+            if let Some(ref pattern) = fingerprint.title {
+                match title {
+                    Some(ref title) if pattern.is_match(title) => matched.push("title"),
+                    _ => continue,
+                }
+            }
+
+            if fingerprint.headers.len() > 0 {
+                let all_match = fingerprint.headers.iter().all(|&(ref header, ref pattern)|
+                    headers.iter().any(|&(ref name, ref value)| name == header && pattern.is_match(value)));
+                match all_match {
+                    true => matched.push("headers"),
+                    false => continue,
+                }
+            }
+
+            if let Some((minimum, maximum)) = fingerprint.content_length {
+                match content_length {
+                    Some(length) if length >= minimum && length <= maximum => matched.push("content_length"),
+                    _ => continue,
+                }
+            }
+
+            let matched: Vec<Bson> = matched.into_iter().map(|x| Bson::String(x.to_owned())).collect();
+            let mut details = doc!(
+                "fingerprint" => (fingerprint.name.to_owned()),
+                "matched" => matched
+            );
+
+            if let Some(ref title) = title {
+                details.insert("title", title.to_owned());
+            }
+
+            if let Some(content_length) = content_length {
+                details.insert("content_length", content_length);
+            }
+
+            let status = format!("{}:{}", self.status, fingerprint.name);
+            let flag = try!(Flag::with_details(document, &status, &self.name, details));
+            self.flag_tx.send(flag);
+        }
+
+        Ok(())
+    }
+}
+
+fn compile_regex(pattern: &str, name: &str) -> Result<Regex, TipupError> {
+    match RegexBuilder::new(pattern).case_insensitive(true).build() {
+        Ok(regex) => Ok(regex),
+        Err(e) => Err(TipupError::from(format!("failed to compile regular expression '{}' in fingerprint '{}': {}", pattern, name, e))),
+    }
+}
+
+//headers may be stored as a document or as an array of "Name: value" strings
+fn parse_headers(headers: &Bson) -> Vec<(String, String)> {
+    match *headers {
+        Bson::Document(ref document) => document.iter().filter_map(|(name, value)| match *value {
+            Bson::String(ref value) => Some((name.to_lowercase(), value.to_owned())),
+            _ => None,
+        }).collect(),
+        Bson::Array(ref array) => array.iter().filter_map(|x| match *x {
+            Bson::String(ref header) => {
+                let mut split = header.splitn(2, ':');
+                match (split.next(), split.next()) {
+                    (Some(name), Some(value)) => Some((name.trim().to_lowercase(), value.trim().to_owned())),
+                    _ => None,
+                }
+            },
+            _ => None,
+        }).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn parse_title(body: &str) -> Option<String> {
+    //ascii lowercase keeps byte offsets valid for the original body
+    let lowercase = body.to_ascii_lowercase();
+    let start = match lowercase.find("<title") {
+        Some(index) => match lowercase[index..].find('>') {
+            Some(offset) => index + offset + 1,
+            None => return None,
+        },
+        None => return None,
+    };
+
+    match lowercase[start..].find("</title") {
+        Some(length) => Some(body[start..start + length].trim().to_owned()),
+        None => None,
+    }
+}
+
+fn as_i64(value: &Bson) -> Option<i64> {
+    match *value {
+        Bson::I32(i) => Some(i as i64),
+        Bson::I64(i) => Some(i),
+        Bson::FloatingPoint(f) => Some(f as i64),
+        _ => None,
+    }
+}
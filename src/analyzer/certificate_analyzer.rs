@@ -0,0 +1,321 @@
+use bson::{self, Bson, Document};
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+use regex::Regex;
+
+use analyzer::{self, Analyzer};
+use error::TipupError;
+use flag_manager::Flag;
+use result_window;
+
+use std::collections::HashMap;
+
+//flags self-signed certificates, unexpected issuers and certificates only one vantage sees
+pub struct CertificateAnalyzer {
+    name: String,
+    status: String,
+    fingerprint_field: Vec<String>,
+    issuer_field: Vec<String>,
+    subject_field: Vec<String>,
+    expected_issuers: Option<Vec<Regex>>,
+    minimum_vantages: usize,
+    warmup_samples: usize,
+    maximum_certificates: usize,
+    maximum_vantages: usize,
+    domains: HashMap<String, DomainCertificates>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct CertificateParameters {
+    pub fingerprint_field: Option<Vec<String>>,
+    pub issuer_field: Option<Vec<String>>,
+    pub subject_field: Option<Vec<String>>,
+    pub expected_issuers: Option<Vec<String>>,
+    pub minimum_vantages: Option<usize>,
+    pub warmup_samples: Option<usize>,
+    pub maximum_certificates: Option<usize>,
+    pub maximum_vantages: Option<usize>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct DomainCertificates {
+    samples: usize,
+    //vantages measuring the domain, least recently seen first
+    vantages: Vec<String>,
+    certificates: Vec<CertificateEntry>,
+    //flagged certificates, learned once enough vantages see them unless self-signed
+    #[serde(default)]
+    candidates: Vec<CertificateEntry>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct CertificateEntry {
+    fingerprint: String,
+    issuer: String,
+    vantages: Vec<String>,
+    #[serde(default)]
+    last_sample: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+struct DomainEntry {
+    measurement_domain: String,
+    certificates: DomainCertificates,
+}
+
+impl DomainCertificates {
+    fn learned_issuers(&self, minimum_vantages: usize) -> Vec<String> {
+        let mut issuer_vantages: HashMap<&str, Vec<&str>> = HashMap::new();
+        for certificate in self.certificates.iter() {
+            let vantages = issuer_vantages.entry(&certificate.issuer).or_insert(Vec::new());
+            for vantage in certificate.vantages.iter() {
+                if !vantages.contains(&vantage.as_str()) {
+                    vantages.push(vantage);
+                }
+            }
+        }
+
+        let mut issuers: Vec<String> = issuer_vantages.iter()
+            .filter(|&(_, vantages)| vantages.len() >= minimum_vantages)
+            .map(|(issuer, _)| issuer.to_string())
+            .collect();
+        issuers.sort();
+        issuers
+    }
+
+    fn observe_vantage(&mut self, hostname: &str, maximum_vantages: usize) {
+        self.samples += 1;
+        touch(&mut self.vantages, hostname, maximum_vantages);
+    }
+
+    fn is_learned(&self, fingerprint: &str) -> bool {
+        self.certificates.iter().any(|x| x.fingerprint == fingerprint)
+    }
+
+    fn learn(&mut self, fingerprint: &str, issuer: &str, hostname: &str, maximum_certificates: usize, maximum_vantages: usize) -> usize {
+        observe(&mut self.certificates, fingerprint, issuer, hostname, self.samples, maximum_certificates, maximum_vantages)
+    }
+
+    //records a flagged certificate and returns the number of vantages that saw it
+    fn learn_candidate(&mut self, fingerprint: &str, issuer: &str, hostname: &str, maximum_certificates: usize, maximum_vantages: usize) -> usize {
+        observe(&mut self.candidates, fingerprint, issuer, hostname, self.samples, maximum_certificates, maximum_vantages)
+    }
+
+    fn promote(&mut self, fingerprint: &str, maximum_certificates: usize) {
+        if let Some(index) = self.candidates.iter().position(|x| x.fingerprint == fingerprint) {
+            let entry = self.candidates.remove(index);
+            self.certificates.push(entry);
+            evict(&mut self.certificates, maximum_certificates);
+        }
+    }
+}
+
+//adds the vantage to the certificate entry and returns the number of vantages that saw the certificate
+fn observe(entries: &mut Vec<CertificateEntry>, fingerprint: &str, issuer: &str, hostname: &str, sample: usize, maximum_certificates: usize, maximum_vantages: usize) -> usize {
+    if let Some(entry) = entries.iter_mut().find(|x| x.fingerprint == fingerprint) {
+        touch(&mut entry.vantages, hostname, maximum_vantages);
+        entry.last_sample = sample;
+        return entry.vantages.len();
+    }
+
+    entries.push(CertificateEntry {
+        fingerprint: fingerprint.to_owned(),
+        issuer: issuer.to_owned(),
+        vantages: vec!(hostname.to_owned()),
+        last_sample: sample,
+    });
+
+    evict(entries, maximum_certificates);
+    1
+}
+
+//removes the least recently seen certificates beyond maximum_certificates
+fn evict(entries: &mut Vec<CertificateEntry>, maximum_certificates: usize) {
+    while entries.len() > maximum_certificates {
+        let index = match entries.iter().enumerate().min_by_key(|&(_, x)| x.last_sample) {
+            Some((index, _)) => index,
+            None => return,
+        };
+
+        entries.remove(index);
+    }
+}
+
+//moves the vantage to the end of the list, dropping the least recently seen beyond maximum_vantages
+fn touch(vantages: &mut Vec<String>, hostname: &str, maximum_vantages: usize) {
+    vantages.retain(|x| x != hostname);
+    vantages.push(hostname.to_owned());
+    while vantages.len() > maximum_vantages {
+        vantages.remove(0);
+    }
+}
+
+impl CertificateAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &CertificateParameters, flag_tx: Sender<Flag>) -> Result<CertificateAnalyzer, TipupError> {
+        //parse parameters
+        let fingerprint_field = try!(analyzer::parse_path(&parameters.fingerprint_field, "fingerprint_field", &["tls", "fingerprint"], name));
+        let issuer_field = try!(analyzer::parse_path(&parameters.issuer_field, "issuer_field", &["tls", "issuer"], name));
+        let subject_field = try!(analyzer::parse_path(&parameters.subject_field, "subject_field", &["tls", "subject"], name));
+
+        let expected_issuers = match parameters.expected_issuers.clone() {
+            Some(patterns) => {
+                let mut expected_issuers = Vec::new();
+                for pattern in patterns.iter() {
+                    match Regex::new(pattern) {
+                        Ok(regex) => expected_issuers.push(regex),
+                        Err(e) => return Err(TipupError::from(format!("failed to compile 'expected_issuers' pattern '{}' in analyzer '{}': {}", pattern, name, e))),
+                    }
+                }
+
+                Some(expected_issuers)
+            },
+            None => None,
+        };
+
+        let minimum_vantages = try!(analyzer::parse_usize(parameters.minimum_vantages, "minimum_vantages", 2, 2, name));
+        let warmup_samples = try!(analyzer::parse_usize(parameters.warmup_samples, "warmup_samples", 10, 1, name));
+        let maximum_certificates = try!(analyzer::parse_usize(parameters.maximum_certificates, "maximum_certificates", 64, 1, name));
+        let maximum_vantages = try!(analyzer::parse_usize(parameters.maximum_vantages, "maximum_vantages", 256, minimum_vantages, name));
+
+        Ok(
+            CertificateAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                fingerprint_field: fingerprint_field,
+                issuer_field: issuer_field,
+                subject_field: subject_field,
+                expected_issuers: expected_issuers,
+                minimum_vantages: minimum_vantages,
+                warmup_samples: warmup_samples,
+                maximum_certificates: maximum_certificates,
+                maximum_vantages: maximum_vantages,
+                domains: HashMap::new(),
+                flag_tx: flag_tx,
+            }
+        )
+    }
+}
+
+impl Analyzer for CertificateAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve variables from document
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let fingerprint = match result_window::get_field(&self.fingerprint_field, document) {
+            Some(&Bson::String(ref fingerprint)) => fingerprint.to_lowercase().replace(":", ""),
+            _ => return Ok(()),
+        };
+
+        let issuer = match result_window::get_field(&self.issuer_field, document) {
+            Some(&Bson::String(ref issuer)) => issuer.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let subject = match result_window::get_field(&self.subject_field, document) {
+            Some(&Bson::String(ref subject)) => Some(subject.to_owned()),
+            _ => None,
+        };
+
+        //check the certificate against what other vantages have seen before learning from it
+        let mut reasons = Vec::new();
+        let (learned_issuers, fingerprint_vantages);
+        {
+            let certificates = self.domains.entry(domain.clone()).or_insert(DomainCertificates::default());
+            learned_issuers = certificates.learned_issuers(self.minimum_vantages);
+
+            if subject.as_ref() == Some(&issuer) {
+                reasons.push("self_signed");
+            }
+
+            let issuer_expected = match self.expected_issuers {
+                Some(ref expected_issuers) => expected_issuers.iter().any(|x| x.is_match(&issuer)),
+                None => certificates.samples < self.warmup_samples || learned_issuers.contains(&issuer),
+            };
+
+            if !issuer_expected {
+                reasons.push("unexpected_issuer");
+            }
+
+            //flagged certificates are never learned, those with an unexpected issuer only once enough vantages see them
+            let trusted = certificates.samples >= self.warmup_samples;
+            certificates.observe_vantage(&hostname, self.maximum_vantages);
+            fingerprint_vantages = if reasons.len() == 0 || certificates.is_learned(&fingerprint) {
+                certificates.learn(&fingerprint, &issuer, &hostname, self.maximum_certificates, self.maximum_vantages)
+            } else {
+                let vantages = certificates.learn_candidate(&fingerprint, &issuer, &hostname, self.maximum_certificates, self.maximum_vantages);
+                if vantages >= self.minimum_vantages && !reasons.contains(&"self_signed") {
+                    certificates.promote(&fingerprint, self.maximum_certificates);
+                    if self.expected_issuers.is_none() {
+                        reasons.retain(|x| *x != "unexpected_issuer");
+                    }
+                }
+
+                vantages
+            };
+
+            //a certificate only one vantage sees while others measure the domain
+
+            if trusted && fingerprint_vantages == 1 && certificates.vantages.len() >= self.minimum_vantages {
+                reasons.push("single_vantage");
+            }
+        }
+
+        if reasons.len() > 0 {
+            let reasons: Vec<Bson> = reasons.into_iter().map(|x| Bson::String(x.to_owned())).collect();
+            let learned_issuers: Vec<Bson> = learned_issuers.into_iter().map(Bson::String).collect();
+            let mut details = doc!(
+                "reasons" => reasons,
+                "fingerprint" => fingerprint,
+                "issuer" => issuer,
+                "expected_issuers" => learned_issuers,
+                "fingerprint_vantages" => (fingerprint_vantages as i64)
+            );
+
+            if let Some(subject) = subject {
+                details.insert("subject", subject);
+            }
+
+            let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
+            self.flag_tx.send(flag);
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Option<Document>, TipupError> {
+        let entries: Vec<DomainEntry> = self.domains.iter().map(|(domain, certificates)| DomainEntry {
+            measurement_domain: domain.to_owned(),
+            certificates: certificates.clone(),
+        }).collect();
+
+        match bson::to_bson(&entries) {
+            Ok(entries) => Ok(Some(doc!("domains" => entries))),
+            Err(e) => Err(TipupError::from(format!("failed to encode certificates: {}", e))),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        let entries: Vec<DomainEntry> = match snapshot.get("domains") {
+            Some(domains) => match bson::from_bson(domains.clone()) {
+                Ok(entries) => entries,
+                Err(e) => return Err(TipupError::from(format!("failed to decode certificates: {}", e))),
+            },
+            None => return Err(TipupError::from("failed to parse 'domains' from certificate snapshot")),
+        };
+
+        for entry in entries {
+            self.domains.insert(entry.measurement_domain, entry.certificates);
+        }
+
+        Ok(())
+    }
+}
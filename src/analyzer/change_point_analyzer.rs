@@ -0,0 +1,225 @@
+use bson::{Bson, Document};
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+
+use analyzer::{self, Analyzer};
+use error::TipupError;
+use flag_manager::Flag;
+use result_window::{self, ChangePointWindow, ResultWindow};
+
+use std::sync::{Arc, RwLock};
+
+//detects sustained shifts per vantage and domain with an ewma and a two-sided cusum of values
+//standardized against a learned baseline
+pub struct ChangePointAnalyzer {
+    name: String,
+    status: String,
+    variable_name: Vec<String>,
+    lambda: f64,
+    k: f64,
+    h: f64,
+    warmup_samples: usize,
+    clear_threshold: f64,
+    baseline_lambda: f64,
+    change_point_window: Arc<RwLock<ChangePointWindow>>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct ChangePointParameters {
+    pub variable_name: Option<Vec<String>>,
+    pub lambda: Option<f64>,
+    pub k: Option<f64>,
+    pub h: Option<f64>,
+    pub warmup_samples: Option<usize>,
+    pub clear_threshold: Option<f64>,
+    pub baseline_lambda: Option<f64>,
+}
+
+impl ChangePointAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &ChangePointParameters, result_window: Arc<RwLock<ResultWindow>>, flag_tx: Sender<Flag>) -> Result<ChangePointAnalyzer, TipupError> {
+        //parse parameters
+        let variable_name = try!(analyzer::parse_variable_name(&parameters.variable_name, name));
+        let lambda = try!(analyzer::parse_positive_f64(parameters.lambda, "lambda", 0.2, name));
+        if lambda > 1.0 {
+            return Err(TipupError::from(format!("'lambda' parameter must be in (0, 1] in analyzer '{}'", name)));
+        }
+
+        let k = try!(analyzer::parse_positive_f64(parameters.k, "k", 0.5, name));
+        let h = try!(analyzer::parse_positive_f64(parameters.h, "h", 5.0, name));
+        let warmup_samples = try!(analyzer::parse_usize(parameters.warmup_samples, "warmup_samples", 20, 2, name));
+        let clear_threshold = try!(analyzer::parse_positive_f64(parameters.clear_threshold, "clear_threshold", 0.5, name));
+        let baseline_lambda = try!(analyzer::parse_positive_f64(parameters.baseline_lambda, "baseline_lambda", 0.01, name));
+        if baseline_lambda >= 1.0 {
+            return Err(TipupError::from(format!("'baseline_lambda' parameter must be in (0, 1) in analyzer '{}'", name)));
+        }
+
+        let change_point_window;
+        {
+            let mut result_window = result_window.write().unwrap();
+            change_point_window = try!(result_window.register_change_point(name));
+        }
+
+        Ok(
+            ChangePointAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                variable_name: variable_name,
+                lambda: lambda,
+                k: k,
+                h: h,
+                warmup_samples: warmup_samples,
+                clear_threshold: clear_threshold,
+                baseline_lambda: baseline_lambda,
+                change_point_window: change_point_window,
+                flag_tx: flag_tx,
+            }
+        )
+    }
+}
+
+impl Analyzer for ChangePointAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve variables from document
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let value = match result_window::get_value(&self.variable_name, document) {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+
+        let mut change_point_window = self.change_point_window.write().unwrap();
+        let state = change_point_window.get_state_mut(&hostname, &domain);
+
+        //learn baseline using welford's online algorithm
+        if state.count < self.warmup_samples {
+            state.count += 1;
+            let delta = value - state.baseline_mean;
+            state.baseline_mean += delta / state.count as f64;
+            state.baseline_sum_squares += delta * (value - state.baseline_mean);
+            return Ok(());
+        }
+
+        let std_dev = state.baseline_std_dev().max(analyzer::deviation_floor(state.baseline_mean));
+
+        //update ewma and cusum statistics with standardized value
+        let z_score = (value - state.baseline_mean) / std_dev;
+        state.ewma = (self.lambda * z_score) + ((1.0 - self.lambda) * state.ewma);
+        state.cusum_upper = f64::max(0.0, state.cusum_upper + z_score - self.k);
+        state.cusum_lower = f64::max(0.0, state.cusum_lower - z_score - self.k);
+
+        //a shift that persists for warmup_samples values becomes the new baseline
+        let direction = if state.ewma >= 0.0 { "upper" } else { "lower" };
+        let (mut baseline_mean, mut baseline_std_dev, ewma) = (state.baseline_mean, std_dev, state.baseline_mean + (state.ewma * std_dev));
+        let (cusum_upper, cusum_lower) = (state.cusum_upper, state.cusum_lower);
+        let transition = if !state.shifted && (cusum_upper > self.h || cusum_lower > self.h) {
+            state.shifted = true;
+            Some(("raised", false))
+        } else if state.shifted && state.ewma.abs() < self.clear_threshold {
+            state.reset_shift();
+            Some(("cleared", false))
+        } else if state.shifted {
+            if state.learn_shift(value, self.warmup_samples) {
+                state.adopt_shift();
+                baseline_mean = state.baseline_mean;
+                baseline_std_dev = state.baseline_std_dev().max(analyzer::deviation_floor(state.baseline_mean));
+                Some(("cleared", true))
+            } else {
+                None
+            }
+        } else {
+            state.adapt_baseline(value, self.baseline_lambda);
+            None
+        };
+
+        if let Some((transition, adopted)) = transition {
+            let details = doc!(
+                "state" => transition,
+                "adopted" => adopted,
+                "direction" => direction,
+                "value" => value,
+                "baseline_mean" => baseline_mean,
+                "baseline_std_dev" => baseline_std_dev,
+                "ewma" => ewma,
+                "cusum_upper" => cusum_upper,
+                "cusum_lower" => cusum_lower
+            );
+
+            let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
+            self.flag_tx.send(flag);
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Option<Document>, TipupError> {
+        let change_point_window = self.change_point_window.read().unwrap();
+        Ok(Some(try!(change_point_window.snapshot())))
+    }
+
+    fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        let mut change_point_window = self.change_point_window.write().unwrap();
+        change_point_window.restore(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::oid::ObjectId;
+    use chan;
+
+    use analyzer::Analyzer;
+    use flag_manager::Flag;
+    use result_window::ResultWindow;
+    use super::{ChangePointAnalyzer, ChangePointParameters};
+
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn persistent_shift_adopted() {
+        let parameters = ChangePointParameters {
+            variable_name: Some(vec!("total_time".to_owned())),
+            warmup_samples: Some(5),
+            ..Default::default()
+        };
+
+        let result_window = Arc::new(RwLock::new(ResultWindow::new()));
+        let (flag_tx, flag_rx) = chan::async();
+        let mut analyzer = ChangePointAnalyzer::new("change_point", "shift", &parameters, result_window, flag_tx).unwrap();
+
+        //a baseline around 100 followed by a lasting move to 200
+        let mut values = vec!(99.0, 101.0, 99.0, 101.0, 100.0);
+        values.extend(vec!(200.0; 15));
+        for value in values {
+            let document = doc!(
+                "_id" => (ObjectId::new().unwrap()),
+                "vantage_hostname" => "vantage",
+                "measurement_domain" => "example.com",
+                "total_time" => value
+            );
+
+            analyzer.process_measurement(&document).unwrap();
+        }
+
+        drop(analyzer);
+        let flags: Vec<Flag> = flag_rx.iter().collect();
+        assert_eq!(flags.len(), 2);
+
+        let raised = flags[0].details.as_ref().unwrap();
+        assert_eq!(raised.get_str("state").unwrap(), "raised");
+        assert_eq!(raised.get_bool("adopted").unwrap(), false);
+
+        let cleared = flags[1].details.as_ref().unwrap();
+        assert_eq!(cleared.get_str("state").unwrap(), "cleared");
+        assert_eq!(cleared.get_bool("adopted").unwrap(), true);
+        assert_eq!(cleared.get_f64("baseline_mean").unwrap(), 200.0);
+    }
+}
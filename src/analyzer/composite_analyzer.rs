@@ -0,0 +1,272 @@
+use bson::{Bson, Document};
+use bson::ordered::OrderedDocument;
+use chan::{Receiver, Sender};
+use mongodb::db::Database;
+
+use analyzer::{self, Analyzer};
+use error::TipupError;
+use flag_manager::Flag;
+use pipe::ClassMatcher;
+use result_window::ResultWindow;
+use rule::Rule;
+
+use std::sync::{Arc, RwLock};
+
+//combines the flags of child analyzers with a rule expression over child names or a minimum
+//number of children that must flag
+pub struct CompositeAnalyzer {
+    name: String,
+    status: String,
+    children: Vec<CompositeChild>,
+    policy: Policy,
+    child_rx: Receiver<Flag>,
+    restored: Vec<String>,
+    result_window: Arc<RwLock<ResultWindow>>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct CompositeParameters {
+    pub analyzers: Option<Vec<String>>,
+    pub expression: Option<String>,
+    pub minimum: Option<usize>,
+}
+
+pub struct CompositeChild {
+    pub name: String,
+    pub measurement_class: ClassMatcher,
+    pub filter: Option<Rule>,
+    pub analyzer: Box<Analyzer>,
+}
+
+enum Policy {
+    Expression(Rule),
+    Minimum(usize),
+}
+
+impl CompositeAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &CompositeParameters, children: Vec<CompositeChild>, child_rx: Receiver<Flag>, result_window: Arc<RwLock<ResultWindow>>, flag_tx: Sender<Flag>) -> Result<CompositeAnalyzer, TipupError> {
+        //parse parameters
+        let child_names: Vec<String> = children.iter().map(|x| x.name.to_owned()).collect();
+        let policy = match (&parameters.expression, parameters.minimum) {
+            (&Some(_), Some(_)) => return Err(TipupError::from(format!("only one of 'expression' and 'minimum' may be set in analyzer '{}'", name))),
+            (&Some(ref expression), None) => {
+                let rule = match Rule::parse(expression) {
+                    Ok(rule) => rule,
+                    Err(e) => return Err(TipupError::from(format!("failed to parse 'expression' parameter in analyzer '{}': {}", name, e))),
+                };
+
+                //catch misspelled children, which would otherwise never flag
+                for path in rule.paths() {
+                    if !child_names.contains(&path) {
+                        return Err(TipupError::from(format!("'expression' references '{}' which is not a child of analyzer '{}'", path, name)));
+                    }
+                }
+
+                Policy::Expression(rule)
+            },
+            (&None, _) => {
+                let minimum = try!(analyzer::parse_usize(parameters.minimum, "minimum", children.len(), 1, name));
+                if minimum > children.len() {
+                    return Err(TipupError::from(format!("'minimum' parameter exceeds the number of children in analyzer '{}'", name)));
+                }
+
+                Policy::Minimum(minimum)
+            },
+        };
+
+        Ok(
+            CompositeAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                children: children,
+                policy: policy,
+                child_rx: child_rx,
+                restored: Vec::new(),
+                result_window: result_window,
+                flag_tx: flag_tx,
+            }
+        )
+    }
+
+    pub fn parse_children(parameters: &CompositeParameters, name: &str) -> Result<Vec<String>, TipupError> {
+        match parameters.analyzers {
+            Some(ref children) if children.len() > 0 => Ok(children.to_owned()),
+            _ => Err(TipupError::from(format!("missing or empty 'analyzers' parameter in analyzer '{}'", name))),
+        }
+    }
+
+    fn evaluate_flags(&self) {
+        //group child flags by measurement, preserving the order they were raised
+        let mut groups: Vec<Vec<Flag>> = Vec::new();
+        let child_rx = &self.child_rx;
+        loop {
+            let mut flag = None;
+            chan_select! {
+                default => {},
+                child_rx.recv() -> child_flag => {
+                    flag = child_flag;
+                },
+            }
+
+            let flag = match flag {
+                Some(flag) => flag,
+                None => break,
+            };
+
+            match groups.iter().position(|x| x[0].measurement_id == flag.measurement_id) {
+                Some(index) => groups[index].push(flag),
+                None => groups.push(vec!(flag)),
+            }
+        }
+
+        for flags in groups {
+            let mut flagged = Vec::new();
+            for flag in flags.iter() {
+                if !flagged.contains(&flag.analyzer) {
+                    flagged.push(flag.analyzer.to_owned());
+                }
+            }
+
+            let matched = match self.policy {
+                Policy::Expression(ref rule) => {
+                    let mut votes = Document::new();
+                    for child in self.children.iter() {
+                        votes.insert(child.name.to_owned(), flagged.contains(&child.name));
+                    }
+
+                    rule.evaluate(&votes)
+                },
+                Policy::Minimum(minimum) => flagged.len() >= minimum,
+            };
+
+            if !matched {
+                continue;
+            }
+
+            let mut child_flags = Vec::new();
+            for flag in flags.iter() {
+                let mut child_flag = doc!(
+                    "analyzer" => (flag.analyzer.to_owned()),
+                    "status" => (flag.status.to_owned())
+                );
+
+                if let Some(ref details) = flag.details {
+                    child_flag.insert("details", details.clone());
+                }
+
+                child_flags.push(Bson::Document(child_flag));
+            }
+
+            let flagged: Vec<Bson> = flagged.into_iter().map(Bson::String).collect();
+            let mut flag = Flag::from_measurement_id(flags[0].measurement_id.clone(), &self.status, &self.name);
+            flag.details = Some(doc!("analyzers" => flagged, "flags" => child_flags));
+            self.flag_tx.send(flag);
+        }
+    }
+}
+
+impl Analyzer for CompositeAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        let measurement_class = match document.get("measurement_class") {
+            Some(&Bson::String(ref measurement_class)) => measurement_class.to_owned(),
+            _ => return Ok(()),
+        };
+
+        //every child sees the measurement before the flags are combined
+        let mut result = Ok(());
+        for child in self.children.iter_mut() {
+            //children are filtered the same way the pipe filters analyzers
+            let accepted = child.measurement_class.matches(&measurement_class) && match child.filter {
+                Some(ref filter) => filter.evaluate(document),
+                None => true,
+            };
+
+            if !accepted {
+                continue;
+            }
+
+            if let Err(e) = child.analyzer.process_measurement(document) {
+                result = Err(TipupError::from(format!("child analyzer '{}' of '{}' failed: {}", child.name, self.name, e)));
+            }
+
+            //children are not in the pipe so their windows are updated here
+            if let Err(e) = self.result_window.read().unwrap().add_result(&child.name, document) {
+                result = Err(TipupError::from(format!("failed to update windows of child analyzer '{}' of '{}': {}", child.name, self.name, e)));
+            }
+        }
+
+        self.evaluate_flags();
+        result
+    }
+
+    fn initialize(&mut self, db: &Database) -> Result<(), TipupError> {
+        //children restored from the snapshot are already warm
+        let restored = &self.restored;
+        for child in self.children.iter_mut().filter(|x| !restored.contains(&x.name)) {
+            try!(child.analyzer.initialize(db));
+        }
+
+        Ok(())
+    }
+
+    fn tick(&mut self, now: i64) -> Result<(), TipupError> {
+        for child in self.children.iter_mut() {
+            try!(child.analyzer.tick(now));
+        }
+
+        self.evaluate_flags();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), TipupError> {
+        for child in self.children.iter_mut() {
+            try!(child.analyzer.flush());
+        }
+
+        self.evaluate_flags();
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Option<Document>, TipupError> {
+        //children are snapshotted inside the composite since they are not registered in the pipe
+        let mut snapshots = Vec::new();
+        for child in self.children.iter() {
+            if let Some(snapshot) = try!(child.analyzer.snapshot()) {
+                snapshots.push(Bson::Document(doc!("analyzer" => (child.name.to_owned()), "snapshot" => snapshot)));
+            }
+        }
+
+        if snapshots.len() == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(doc!("children" => snapshots)))
+    }
+
+    fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        let snapshots = match snapshot.get("children") {
+            Some(&Bson::Array(ref snapshots)) => snapshots,
+            _ => return Err(TipupError::from(format!("failed to parse 'children' from snapshot in analyzer '{}'", self.name))),
+        };
+
+        let mut restored = Vec::new();
+        for snapshot in snapshots {
+            let (name, snapshot) = match *snapshot {
+                Bson::Document(ref document) => match (document.get("analyzer"), document.get("snapshot")) {
+                    (Some(&Bson::String(ref name)), Some(&Bson::Document(ref snapshot))) => (name, snapshot),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            if let Some(child) = self.children.iter_mut().find(|x| &x.name == name) {
+                try!(child.analyzer.restore(snapshot));
+                restored.push(name.to_owned());
+            }
+        }
+
+        self.restored = restored;
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use bson::Bson;
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+
+use analyzer::{self, Analyzer};
+use error::TipupError;
+use flag_manager::Flag;
+use result_window::{self, MeasurementView, ResultWindow};
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+//flags a vantage whose outcome disagrees with the consensus of other vantages measuring the
+//same domain within a time slice
+pub struct ConsensusAnalyzer {
+    name: String,
+    status: String,
+    outcome_field: Option<Vec<String>>,
+    error_field: String,
+    time_slice_seconds: i64,
+    minimum_vantages: usize,
+    consensus: f64,
+    measurement_view: Arc<RwLock<MeasurementView>>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct ConsensusParameters {
+    pub outcome_field: Option<Vec<String>>,
+    pub error_field: Option<String>,
+    pub time_slice_seconds: Option<usize>,
+    pub minimum_vantages: Option<usize>,
+    pub consensus: Option<f64>,
+}
+
+impl ConsensusAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &ConsensusParameters, result_window: Arc<RwLock<ResultWindow>>, flag_tx: Sender<Flag>) -> Result<ConsensusAnalyzer, TipupError> {
+        //parse parameters
+        let outcome_field = parameters.outcome_field.clone();
+        let error_field = parameters.error_field.clone().unwrap_or("measurement_error_message".to_owned());
+        let time_slice_seconds = try!(analyzer::parse_usize(parameters.time_slice_seconds, "time_slice_seconds", 3600, 1, name));
+        let minimum_vantages = try!(analyzer::parse_usize(parameters.minimum_vantages, "minimum_vantages", 3, 1, name));
+        let consensus = try!(analyzer::parse_positive_f64(parameters.consensus, "consensus", 0.75, name));
+        if consensus <= 0.5 || consensus > 1.0 {
+            return Err(TipupError::from(format!("'consensus' parameter must be in (0.5, 1] in analyzer '{}'", name)));
+        }
+
+        let measurement_view;
+        {
+            let mut result_window = result_window.write().unwrap();
+            measurement_view = try!(result_window.register_measurement_view(name));
+        }
+
+        Ok(
+            ConsensusAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                outcome_field: outcome_field,
+                error_field: error_field,
+                time_slice_seconds: time_slice_seconds as i64,
+                minimum_vantages: minimum_vantages,
+                consensus: consensus,
+                measurement_view: measurement_view,
+                flag_tx: flag_tx,
+            }
+        )
+    }
+
+    fn get_outcome(&self, document: &OrderedDocument) -> String {
+        if document.contains_key(&self.error_field) {
+            return String::from("error");
+        }
+
+        match self.outcome_field {
+            Some(ref outcome_field) => match result_window::get_field(outcome_field, document) {
+                Some(&Bson::String(ref value)) => value.to_owned(),
+                Some(value) => value.to_string(),
+                None => String::from("missing"),
+            },
+            None => String::from("success"),
+        }
+    }
+}
+
+impl Analyzer for ConsensusAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve variables from document
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let measurement_class = match document.get("measurement_class") {
+            Some(&Bson::String(ref measurement_class)) => measurement_class.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let timestamp = match document.get("timestamp") {
+            Some(&Bson::I64(timestamp)) => timestamp,
+            _ => return Ok(()),
+        };
+
+        //tally outcomes of other vantages within the time slice
+        let mut outcomes: HashMap<String, usize> = HashMap::new();
+        let mut vantages = 0;
+        {
+            let measurement_view = self.measurement_view.read().unwrap();
+            let measurements = match measurement_view.get_measurements(&measurement_class, &domain) {
+                Some(measurements) => measurements,
+                None => return Ok(()),
+            };
+
+            for (other_hostname, other_document) in measurements.iter() {
+                if *other_hostname == hostname {
+                    continue;
+                }
+
+                match other_document.get("timestamp") {
+                    Some(&Bson::I64(other_timestamp)) if (timestamp - other_timestamp).abs() <= self.time_slice_seconds => (),
+                    _ => continue,
+                }
+
+                *outcomes.entry(self.get_outcome(other_document)).or_insert(0) += 1;
+                vantages += 1;
+            }
+        }
+
+        if vantages < self.minimum_vantages {
+            return Ok(());
+        }
+
+        //determine consensus outcome
+        let (consensus_outcome, agreeing) = match outcomes.iter().max_by_key(|&(_, count)| *count) {
+            Some((outcome, count)) => (outcome.to_owned(), *count),
+            None => return Ok(()),
+        };
+
+        let fraction = agreeing as f64 / vantages as f64;
+        if fraction < self.consensus {
+            return Ok(());
+        }
+
+        //flag if this vantage disagrees with the consensus
+        let outcome = self.get_outcome(document);
+        if outcome != consensus_outcome {
+            let (agreeing, vantages) = (agreeing as i64, vantages as i64);
+            let details = doc!(
+                "outcome" => outcome,
+                "consensus_outcome" => consensus_outcome,
+                "agreeing_vantages" => agreeing,
+                "vantages" => vantages,
+                "consensus" => fraction
+            );
+
+            let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
+            self.flag_tx.send(flag);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use bson::{Bson, Document};
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+
+use analyzer::{self, Analyzer};
+use error::TipupError;
+use flag_manager::Flag;
+use result_window::{self, ContentFeatures, ContentWindow, ResultWindow};
+
+use std::sync::{Arc, RwLock};
+
+//flags response bodies that diverge from a rolling per-domain baseline in size or simhash
+pub struct ContentDriftAnalyzer {
+    name: String,
+    status: String,
+    body_field: Vec<String>,
+    minimum_samples: usize,
+    size_threshold: f64,
+    simhash_threshold: u32,
+    content_window: Arc<RwLock<ContentWindow>>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct ContentDriftParameters {
+    pub body_field: Option<Vec<String>>,
+    pub window_length: Option<usize>,
+    pub minimum_samples: Option<usize>,
+    pub size_threshold: Option<f64>,
+    pub simhash_threshold: Option<usize>,
+}
+
+impl ContentDriftAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &ContentDriftParameters, result_window: Arc<RwLock<ResultWindow>>, flag_tx: Sender<Flag>) -> Result<ContentDriftAnalyzer, TipupError> {
+        //parse parameters
+        let body_field = try!(analyzer::parse_path(&parameters.body_field, "body_field", &["body"], name));
+
+        let window_length = try!(analyzer::parse_usize(parameters.window_length, "window_length", 50, 1, name));
+        let minimum_samples = try!(analyzer::parse_usize(parameters.minimum_samples, "minimum_samples", 10, 1, name));
+        if minimum_samples > window_length {
+            return Err(TipupError::from(format!("'minimum_samples' parameter exceeds 'window_length' in analyzer '{}'", name)));
+        }
+
+        let size_threshold = try!(analyzer::parse_positive_f64(parameters.size_threshold, "size_threshold", 0.5, name));
+        let simhash_threshold = try!(analyzer::parse_usize(parameters.simhash_threshold, "simhash_threshold", 12, 1, name));
+        if simhash_threshold > 64 {
+            return Err(TipupError::from(format!("'simhash_threshold' parameter must be at most 64 in analyzer '{}'", name)));
+        }
+
+        let content_window;
+        {
+            let mut result_window = result_window.write().unwrap();
+            content_window = try!(result_window.register_content(name, &body_field, window_length));
+        }
+
+        Ok(
+            ContentDriftAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                body_field: body_field,
+                minimum_samples: minimum_samples,
+                size_threshold: size_threshold,
+                simhash_threshold: simhash_threshold as u32,
+                content_window: content_window,
+                flag_tx: flag_tx,
+            }
+        )
+    }
+}
+
+impl Analyzer for ContentDriftAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve variables from document
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let features = match result_window::get_field(&self.body_field, document) {
+            Some(&Bson::String(ref body)) => ContentFeatures::new(&hostname, body),
+            _ => return Ok(()),
+        };
+
+        //compare against the baseline, which does not yet contain this measurement
+        let mut content_window = self.content_window.write().unwrap();
+        let baseline = match content_window.get_features(&domain) {
+            Some(baseline) if baseline.len() >= self.minimum_samples => baseline,
+            _ => return Ok(()),
+        };
+
+        if baseline.iter().any(|x| x.sha256 == features.sha256) {
+            return Ok(());
+        }
+
+        let mut sizes: Vec<f64> = baseline.iter().map(|x| x.size as f64).collect();
+        let baseline_size = analyzer::compute_median(&mut sizes);
+        let size_change = if baseline_size > 0.0 {
+            (features.size as f64 - baseline_size) / baseline_size
+        } else if features.size > 0 {
+            1.0
+        } else {
+            0.0
+        };
+
+        let simhash_distance = match baseline.iter().filter_map(|x| features.simhash_distance(x)).min() {
+            Some(simhash_distance) => simhash_distance,
+            None => return Ok(()),
+        };
+
+        let mut reasons = Vec::new();
+        if size_change.abs() > self.size_threshold {
+            reasons.push(Bson::String("size".to_owned()));
+        }
+
+        if simhash_distance > self.simhash_threshold {
+            reasons.push(Bson::String("content".to_owned()));
+        }
+
+        if reasons.len() > 0 {
+            let mut vantages: Vec<&str> = baseline.iter().map(|x| x.vantage_hostname.as_str()).collect();
+            vantages.sort();
+            vantages.dedup();
+
+            let details = doc!(
+                "reasons" => reasons,
+                "size" => (features.size as i64),
+                "baseline_size" => baseline_size,
+                "size_change" => size_change,
+                "simhash_distance" => (simhash_distance as i32),
+                "sha256" => (features.sha256.to_owned()),
+                "baseline_samples" => (baseline.len() as i64),
+                "baseline_vantages" => (vantages.len() as i64)
+            );
+
+            let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
+            self.flag_tx.send(flag);
+            content_window.set_flagged(document);
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Option<Document>, TipupError> {
+        let content_window = self.content_window.read().unwrap();
+        Ok(Some(try!(content_window.snapshot())))
+    }
+
+    fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        let mut content_window = self.content_window.write().unwrap();
+        content_window.restore(snapshot)
+    }
+}
@@ -0,0 +1,321 @@
+use bson::{self, Bson, Document};
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+
+use analyzer::{self, Analyzer};
+use error::TipupError;
+use flag_manager::Flag;
+use result_window;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+//flags dns answers whose prefix has not been seen by enough vantages, or that are bogons
+pub struct DnsAnswerAnalyzer {
+    name: String,
+    status: String,
+    answer_field: Vec<String>,
+    prefix_length_v4: u8,
+    prefix_length_v6: u8,
+    minimum_vantages: usize,
+    warmup_samples: usize,
+    maximum_prefixes: usize,
+    domains: HashMap<String, DomainAnswers>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct DnsAnswerParameters {
+    pub answer_field: Option<Vec<String>>,
+    pub prefix_length_v4: Option<usize>,
+    pub prefix_length_v6: Option<usize>,
+    pub minimum_vantages: Option<usize>,
+    pub warmup_samples: Option<usize>,
+    pub maximum_prefixes: Option<usize>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct DomainAnswers {
+    samples: usize,
+    prefixes: Vec<PrefixEntry>,
+    //unexpected prefixes, learned once enough vantages see them
+    #[serde(default)]
+    candidates: Vec<PrefixEntry>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct PrefixEntry {
+    prefix: String,
+    vantages: Vec<String>,
+    #[serde(default)]
+    last_sample: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+struct DomainEntry {
+    measurement_domain: String,
+    answers: DomainAnswers,
+}
+
+impl DomainAnswers {
+    fn expected(&self, minimum_vantages: usize) -> Vec<String> {
+        self.prefixes.iter()
+            .filter(|x| x.vantages.len() >= minimum_vantages)
+            .map(|x| x.prefix.to_owned())
+            .collect()
+    }
+
+    fn learn(&mut self, prefix: &str, hostname: &str, maximum_prefixes: usize) {
+        observe(&mut self.prefixes, prefix, hostname, self.samples, maximum_prefixes);
+    }
+
+    //records an unexpected prefix, returns true when it was seen at enough vantages to be learned
+    fn learn_candidate(&mut self, prefix: &str, hostname: &str, minimum_vantages: usize, maximum_prefixes: usize) -> bool {
+        let vantages = observe(&mut self.candidates, prefix, hostname, self.samples, maximum_prefixes);
+        if vantages < minimum_vantages {
+            return false;
+        }
+
+        if let Some(index) = self.candidates.iter().position(|x| x.prefix == prefix) {
+            let entry = self.candidates.remove(index);
+            self.prefixes.retain(|x| x.prefix != prefix);
+            self.prefixes.push(entry);
+            evict(&mut self.prefixes, maximum_prefixes);
+        }
+
+        true
+    }
+}
+
+//adds the vantage to the prefix entry and returns the number of vantages that saw the prefix
+fn observe(entries: &mut Vec<PrefixEntry>, prefix: &str, hostname: &str, sample: usize, maximum_prefixes: usize) -> usize {
+    if let Some(entry) = entries.iter_mut().find(|x| x.prefix == prefix) {
+        if !entry.vantages.iter().any(|x| x == hostname) {
+            entry.vantages.push(hostname.to_owned());
+        }
+
+        entry.last_sample = sample;
+        return entry.vantages.len();
+    }
+
+    entries.push(PrefixEntry {
+        prefix: prefix.to_owned(),
+        vantages: vec!(hostname.to_owned()),
+        last_sample: sample,
+    });
+
+    evict(entries, maximum_prefixes);
+    1
+}
+
+//removes the least recently seen prefixes beyond maximum_prefixes
+fn evict(entries: &mut Vec<PrefixEntry>, maximum_prefixes: usize) {
+    while entries.len() > maximum_prefixes {
+        let index = match entries.iter().enumerate().min_by_key(|&(_, x)| x.last_sample) {
+            Some((index, _)) => index,
+            None => return,
+        };
+
+        entries.remove(index);
+    }
+}
+
+impl DnsAnswerAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &DnsAnswerParameters, flag_tx: Sender<Flag>) -> Result<DnsAnswerAnalyzer, TipupError> {
+        //parse parameters
+        let answer_field = try!(analyzer::parse_path(&parameters.answer_field, "answer_field", &["answers"], name));
+
+        let prefix_length_v4 = try!(analyzer::parse_usize(parameters.prefix_length_v4, "prefix_length_v4", 24, 1, name));
+        let prefix_length_v6 = try!(analyzer::parse_usize(parameters.prefix_length_v6, "prefix_length_v6", 48, 1, name));
+        if prefix_length_v4 > 32 || prefix_length_v6 > 128 {
+            return Err(TipupError::from(format!("prefix lengths must be at most 32 for IPv4 and 128 for IPv6 in analyzer '{}'", name)));
+        }
+
+        let minimum_vantages = try!(analyzer::parse_usize(parameters.minimum_vantages, "minimum_vantages", 2, 1, name));
+        let warmup_samples = try!(analyzer::parse_usize(parameters.warmup_samples, "warmup_samples", 10, 1, name));
+        let maximum_prefixes = try!(analyzer::parse_usize(parameters.maximum_prefixes, "maximum_prefixes", 64, 1, name));
+
+        Ok(
+            DnsAnswerAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                answer_field: answer_field,
+                prefix_length_v4: prefix_length_v4 as u8,
+                prefix_length_v6: prefix_length_v6 as u8,
+                minimum_vantages: minimum_vantages,
+                warmup_samples: warmup_samples,
+                maximum_prefixes: maximum_prefixes,
+                domains: HashMap::new(),
+                flag_tx: flag_tx,
+            }
+        )
+    }
+}
+
+impl Analyzer for DnsAnswerAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve variables from document
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
+            _ => return Ok(()),
+        };
+
+        //answers that are not addresses (ex. CNAME targets) are ignored
+        let addresses: Vec<IpAddr> = match result_window::get_field(&self.answer_field, document) {
+            Some(&Bson::String(ref answer)) => answer.parse().into_iter().collect(),
+            Some(&Bson::Array(ref answers)) => answers.iter().filter_map(|x| match *x {
+                Bson::String(ref answer) => answer.parse().ok(),
+                _ => None,
+            }).collect(),
+            _ => return Ok(()),
+        };
+
+        if addresses.len() == 0 {
+            return Ok(());
+        }
+
+        //check answers against the learned prefixes before learning from them
+        let mut unexpected = Vec::new();
+        let mut expected = Vec::new();
+        {
+            let answers = self.domains.entry(domain.clone()).or_insert(DomainAnswers::default());
+            let trusted = answers.samples >= self.warmup_samples;
+            if trusted {
+                expected = answers.expected(self.minimum_vantages);
+            }
+
+            answers.samples += 1;
+            for address in addresses.iter() {
+                let prefix = prefix(address, self.prefix_length_v4, self.prefix_length_v6);
+
+                //flagged answers are never learned, unexpected prefixes only once enough vantages see them
+                let reason = if is_bogon(address) {
+                    Some("bogon")
+                } else if trusted && !expected.contains(&prefix) {
+                    match answers.learn_candidate(&prefix, &hostname, self.minimum_vantages, self.maximum_prefixes) {
+                        true => None,
+                        false => Some("unexpected"),
+                    }
+                } else {
+                    answers.learn(&prefix, &hostname, self.maximum_prefixes);
+                    None
+                };
+
+                if let Some(reason) = reason {
+                    unexpected.push(Bson::Document(doc!(
+                        "answer" => (address.to_string()),
+                        "prefix" => (prefix.to_owned()),
+                        "reason" => reason
+                    )));
+                }
+            }
+        }
+
+        if unexpected.len() > 0 {
+            let expected: Vec<Bson> = expected.into_iter().map(Bson::String).collect();
+            let details = doc!(
+                "unexpected" => unexpected,
+                "expected" => expected
+            );
+
+            let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
+            self.flag_tx.send(flag);
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Option<Document>, TipupError> {
+        let entries: Vec<DomainEntry> = self.domains.iter().map(|(domain, answers)| DomainEntry {
+            measurement_domain: domain.to_owned(),
+            answers: answers.clone(),
+        }).collect();
+
+        match bson::to_bson(&entries) {
+            Ok(entries) => Ok(Some(doc!("domains" => entries))),
+            Err(e) => Err(TipupError::from(format!("failed to encode dns answers: {}", e))),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        let entries: Vec<DomainEntry> = match snapshot.get("domains") {
+            Some(domains) => match bson::from_bson(domains.clone()) {
+                Ok(entries) => entries,
+                Err(e) => return Err(TipupError::from(format!("failed to decode dns answers: {}", e))),
+            },
+            None => return Err(TipupError::from("failed to parse 'domains' from dns answer snapshot")),
+        };
+
+        for entry in entries {
+            self.domains.insert(entry.measurement_domain, entry.answers);
+        }
+
+        Ok(())
+    }
+}
+
+fn prefix(address: &IpAddr, prefix_length_v4: u8, prefix_length_v6: u8) -> String {
+    match *address {
+        IpAddr::V4(ref address) => {
+            let mask = match prefix_length_v4 {
+                0 => 0,
+                length => !0u32 << (32 - length as u32),
+            };
+
+            let network = Ipv4Addr::from(u32::from(*address) & mask);
+            format!("{}/{}", network, prefix_length_v4)
+        },
+        IpAddr::V6(ref address) => {
+            let mut segments = address.segments();
+            for (i, segment) in segments.iter_mut().enumerate() {
+                let bits = (prefix_length_v6 as i32 - (i as i32 * 16)).max(0).min(16);
+                *segment &= match bits {
+                    0 => 0,
+                    bits => !0u16 << (16 - bits as u32),
+                };
+            }
+
+            let network = Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3],
+                segments[4], segments[5], segments[6], segments[7]);
+            format!("{}/{}", network, prefix_length_v6)
+        },
+    }
+}
+
+//private, loopback, link local, shared, documentation, benchmarking, multicast and reserved ranges
+fn is_bogon(address: &IpAddr) -> bool {
+    match *address {
+        IpAddr::V4(ref address) => is_bogon_v4(address),
+        IpAddr::V6(ref address) => {
+            let segments = address.segments();
+            match segments {
+                //ipv4 mapped addresses are checked as ipv4
+                [0, 0, 0, 0, 0, 0xffff, high, low] => is_bogon_v4(&Ipv4Addr::from(((high as u32) << 16) | low as u32)),
+                [0, 0, 0, 0, 0, 0, 0, _] => true,
+                [0x0100, 0, 0, 0, _, _, _, _] => true,
+                [0x2001, 0x0db8, _, _, _, _, _, _] => true,
+                [x, _, _, _, _, _, _, _] => (x & 0xfe00) == 0xfc00 || (x & 0xffc0) == 0xfe80 || (x & 0xff00) == 0xff00,
+            }
+        },
+    }
+}
+
+fn is_bogon_v4(address: &Ipv4Addr) -> bool {
+    let octets = address.octets();
+    match (octets[0], octets[1], octets[2]) {
+        (0, _, _) | (10, _, _) | (127, _, _) => true,
+        (100, x, _) if x >= 64 && x < 128 => true,
+        (169, 254, _) => true,
+        (172, x, _) if x >= 16 && x < 32 => true,
+        (192, 0, 0) | (192, 0, 2) | (192, 168, _) => true,
+        (198, 18, _) | (198, 19, _) | (198, 51, 100) => true,
+        (203, 0, 113) => true,
+        (x, _, _) if x >= 224 => true,
+        _ => false,
+    }
+}
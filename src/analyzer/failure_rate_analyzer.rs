@@ -0,0 +1,268 @@
+use bson::{self, Bson, Document};
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+
+use analyzer::{self, Analyzer};
+use error::TipupError;
+use flag_manager::Flag;
+
+use std::collections::HashMap;
+
+//flags jumps in the recent failure rate of a domain or vantage over its baseline rate
+pub struct FailureRateAnalyzer {
+    name: String,
+    status: String,
+    window_length: usize,
+    baseline_length: usize,
+    minimum_baseline: usize,
+    minimum_failures: usize,
+    z_threshold: f64,
+    clear_threshold: f64,
+    count_retries: bool,
+    domains: HashMap<String, FailureSeries>,
+    vantages: HashMap<String, FailureSeries>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct FailureRateParameters {
+    pub window_length: Option<usize>,
+    pub baseline_length: Option<usize>,
+    pub minimum_baseline: Option<usize>,
+    pub minimum_failures: Option<usize>,
+    pub z_threshold: Option<f64>,
+    pub clear_threshold: Option<f64>,
+    pub count_retries: Option<bool>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct FailureSeries {
+    recent: Vec<bool>,
+    baseline: Vec<bool>,
+    raised: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct FailureEntry {
+    vantage_hostname: Option<String>,
+    measurement_domain: Option<String>,
+    series: FailureSeries,
+}
+
+struct FailureTest {
+    failures: usize,
+    failure_rate: f64,
+    baseline_failure_rate: f64,
+    z_score: f64,
+}
+
+impl FailureSeries {
+    fn add(&mut self, failed: bool, window_length: usize, baseline_length: usize) {
+        self.recent.push(failed);
+        while self.recent.len() > window_length {
+            //outcomes age into the baseline unless it is frozen by a raised flag
+            let outcome = self.recent.remove(0);
+            if !self.raised {
+                self.baseline.push(outcome);
+            }
+        }
+
+        while self.baseline.len() > baseline_length {
+            self.baseline.remove(0);
+        }
+    }
+
+    fn test(&self) -> FailureTest {
+        let (n1, n0) = (self.recent.len() as f64, self.baseline.len() as f64);
+        let x1 = self.recent.iter().filter(|x| **x).count();
+        let x0 = self.baseline.iter().filter(|x| **x).count();
+        let (p1, p0) = (x1 as f64 / n1, x0 as f64 / n0);
+
+        //pooled two-proportion z-test
+        let pooled = (x1 + x0) as f64 / (n1 + n0);
+        let std_error = (pooled * (1.0 - pooled) * ((1.0 / n1) + (1.0 / n0))).sqrt();
+        let z_score = match std_error > 0.0 {
+            true => (p1 - p0) / std_error,
+            false => 0.0,
+        };
+
+        FailureTest {
+            failures: x1,
+            failure_rate: p1,
+            baseline_failure_rate: p0,
+            z_score: z_score,
+        }
+    }
+}
+
+impl FailureRateAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &FailureRateParameters, flag_tx: Sender<Flag>) -> Result<FailureRateAnalyzer, TipupError> {
+        //parse parameters
+        let window_length = try!(analyzer::parse_usize(parameters.window_length, "window_length", 20, 2, name));
+        let baseline_length = try!(analyzer::parse_usize(parameters.baseline_length, "baseline_length", 200, 2, name));
+        let minimum_baseline = try!(analyzer::parse_usize(parameters.minimum_baseline, "minimum_baseline", 50, 1, name));
+        if minimum_baseline > baseline_length {
+            return Err(TipupError::from(format!("'minimum_baseline' parameter exceeds 'baseline_length' in analyzer '{}'", name)));
+        }
+
+        let minimum_failures = try!(analyzer::parse_usize(parameters.minimum_failures, "minimum_failures", 3, 2, name));
+        if minimum_failures > window_length {
+            return Err(TipupError::from(format!("'minimum_failures' parameter exceeds 'window_length' in analyzer '{}'", name)));
+        }
+
+        let z_threshold = try!(analyzer::parse_positive_f64(parameters.z_threshold, "z_threshold", 3.0, name));
+        let clear_threshold = try!(analyzer::parse_positive_f64(parameters.clear_threshold, "clear_threshold", 1.0, name));
+        if clear_threshold > z_threshold {
+            return Err(TipupError::from(format!("'clear_threshold' parameter exceeds 'z_threshold' in analyzer '{}'", name)));
+        }
+
+        let count_retries = parameters.count_retries.unwrap_or(false);
+
+        Ok(
+            FailureRateAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                window_length: window_length,
+                baseline_length: baseline_length,
+                minimum_baseline: minimum_baseline,
+                minimum_failures: minimum_failures,
+                z_threshold: z_threshold,
+                clear_threshold: clear_threshold,
+                count_retries: count_retries,
+                domains: HashMap::new(),
+                vantages: HashMap::new(),
+                flag_tx: flag_tx,
+            }
+        )
+    }
+
+    fn update(&self, series: &mut FailureSeries, failed: bool) -> Option<(&'static str, FailureTest)> {
+        series.add(failed, self.window_length, self.baseline_length);
+        if series.recent.len() < self.window_length || series.baseline.len() < self.minimum_baseline {
+            return None;
+        }
+
+        let test = series.test();
+        if !series.raised && test.z_score > self.z_threshold && test.failures >= self.minimum_failures {
+            series.raised = true;
+            Some(("raised", test))
+        } else if series.raised && test.z_score < self.clear_threshold {
+            series.raised = false;
+            Some(("cleared", test))
+        } else {
+            None
+        }
+    }
+}
+
+impl Analyzer for FailureRateAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve variables from document
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
+            _ => return Ok(()),
+        };
+
+        //bucket the outcome the same way as chimpanzee compute-success-rate
+        let remaining_attempts = match document.get("remaining_attempts") {
+            Some(&Bson::I32(i)) => i as i64,
+            Some(&Bson::I64(i)) => i,
+            _ => 0,
+        };
+
+        let failed = match document.get("measurement_error_message") {
+            Some(_) if remaining_attempts > 0 && !self.count_retries => return Ok(()),
+            Some(_) => true,
+            None => false,
+        };
+
+        let mut transitions = Vec::new();
+        {
+            let mut series = self.domains.remove(&domain).unwrap_or(FailureSeries::default());
+            if let Some((state, test)) = self.update(&mut series, failed) {
+                transitions.push((state, "domain", test));
+            }
+
+            self.domains.insert(domain.clone(), series);
+        }
+
+        {
+            let mut series = self.vantages.remove(&hostname).unwrap_or(FailureSeries::default());
+            if let Some((state, test)) = self.update(&mut series, failed) {
+                transitions.push((state, "vantage", test));
+            }
+
+            self.vantages.insert(hostname.clone(), series);
+        }
+
+        for (state, scope, test) in transitions {
+            let mut details = doc!(
+                "state" => state,
+                "scope" => scope,
+                "failures" => (test.failures as i64),
+                "failure_rate" => (test.failure_rate),
+                "baseline_failure_rate" => (test.baseline_failure_rate),
+                "z_score" => (test.z_score)
+            );
+
+            match scope {
+                "domain" => details.insert("measurement_domain", domain.to_owned()),
+                _ => details.insert("vantage_hostname", hostname.to_owned()),
+            };
+
+            let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
+            self.flag_tx.send(flag);
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Option<Document>, TipupError> {
+        let mut entries = Vec::new();
+        for (domain, series) in self.domains.iter() {
+            entries.push(FailureEntry {
+                vantage_hostname: None,
+                measurement_domain: Some(domain.to_owned()),
+                series: series.clone(),
+            });
+        }
+
+        for (hostname, series) in self.vantages.iter() {
+            entries.push(FailureEntry {
+                vantage_hostname: Some(hostname.to_owned()),
+                measurement_domain: None,
+                series: series.clone(),
+            });
+        }
+
+        match bson::to_bson(&entries) {
+            Ok(entries) => Ok(Some(doc!("series" => entries))),
+            Err(e) => Err(TipupError::from(format!("failed to encode failure series: {}", e))),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        let entries: Vec<FailureEntry> = match snapshot.get("series") {
+            Some(series) => match bson::from_bson(series.clone()) {
+                Ok(entries) => entries,
+                Err(e) => return Err(TipupError::from(format!("failed to decode failure series: {}", e))),
+            },
+            None => return Err(TipupError::from("failed to parse 'series' from failure rate snapshot")),
+        };
+
+        for entry in entries {
+            match (entry.vantage_hostname, entry.measurement_domain) {
+                (_, Some(domain)) => { self.domains.insert(domain, entry.series); },
+                (Some(hostname), None) => { self.vantages.insert(hostname, entry.series); },
+                (None, None) => continue,
+            }
+        }
+
+        Ok(())
+    }
+}
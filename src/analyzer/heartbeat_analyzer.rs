@@ -0,0 +1,350 @@
+use bson::{self, Bson, Document};
+use bson::oid::ObjectId;
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+
+use analyzer::{self, Analyzer};
+use error::TipupError;
+use flag_manager::Flag;
+
+use std::collections::HashMap;
+
+//flags vantages, or domains at a vantage, that stop reporting for tolerance learned intervals
+pub struct HeartbeatAnalyzer {
+    name: String,
+    status: String,
+    expected_interval_seconds: f64,
+    tolerance: f64,
+    minimum_gaps: usize,
+    vantages: HashMap<String, Heartbeat>,
+    domains: HashMap<String, HashMap<String, Heartbeat>>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct HeartbeatParameters {
+    pub expected_interval_seconds: Option<f64>,
+    pub tolerance: Option<f64>,
+    pub minimum_gaps: Option<usize>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct Heartbeat {
+    last_timestamp: i64,
+    last_measurement_id: ObjectId,
+    interval: f64,
+    gaps: usize,
+    quiet: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct HeartbeatEntry {
+    vantage_hostname: String,
+    measurement_domain: Option<String>,
+    heartbeat: Heartbeat,
+}
+
+impl Heartbeat {
+    fn new(timestamp: i64, measurement_id: ObjectId) -> Heartbeat {
+        Heartbeat {
+            last_timestamp: timestamp,
+            last_measurement_id: measurement_id,
+            interval: 0.0,
+            gaps: 0,
+            quiet: false,
+        }
+    }
+
+    fn update(&mut self, timestamp: i64, measurement_id: ObjectId) {
+        if timestamp <= self.last_timestamp {
+            return;
+        }
+
+        //track the interval as a moving average of gaps between measurements
+        let gap = (timestamp - self.last_timestamp) as f64;
+        self.interval = match self.gaps {
+            0 => gap,
+            _ => (0.2 * gap) + (0.8 * self.interval),
+        };
+
+        self.gaps += 1;
+        self.touch(timestamp, measurement_id);
+    }
+
+    //records a measurement without learning from the gap
+    fn touch(&mut self, timestamp: i64, measurement_id: ObjectId) {
+        if timestamp > self.last_timestamp {
+            self.last_timestamp = timestamp;
+            self.last_measurement_id = measurement_id;
+        }
+    }
+
+    fn expected_interval(&self, default: f64, minimum_gaps: usize) -> f64 {
+        match self.gaps >= minimum_gaps {
+            true => self.interval,
+            false => default,
+        }
+    }
+}
+
+impl HeartbeatAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &HeartbeatParameters, flag_tx: Sender<Flag>) -> Result<HeartbeatAnalyzer, TipupError> {
+        //parse parameters
+        let expected_interval_seconds = try!(analyzer::parse_positive_f64(parameters.expected_interval_seconds, "expected_interval_seconds", 3600.0, name));
+        let tolerance = try!(analyzer::parse_positive_f64(parameters.tolerance, "tolerance", 3.0, name));
+        let minimum_gaps = try!(analyzer::parse_usize(parameters.minimum_gaps, "minimum_gaps", 3, 1, name));
+
+        Ok(
+            HeartbeatAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                expected_interval_seconds: expected_interval_seconds,
+                tolerance: tolerance,
+                minimum_gaps: minimum_gaps,
+                vantages: HashMap::new(),
+                domains: HashMap::new(),
+                flag_tx: flag_tx,
+            }
+        )
+    }
+
+    //a vantage measures its domains in rounds, the gaps between its own measurements are mostly
+    //within a round, so it expects the longest interval learned by its domains
+    fn vantage_interval(&self, hostname: &str) -> f64 {
+        let domains = match self.domains.get(hostname) {
+            Some(domains) => domains,
+            None => return self.expected_interval_seconds,
+        };
+
+        domains.values()
+            .filter(|x| x.gaps >= self.minimum_gaps)
+            .map(|x| x.interval)
+            .fold(None, |max: Option<f64>, x| Some(max.map_or(x, |y| y.max(x))))
+            .unwrap_or(self.expected_interval_seconds)
+    }
+
+    fn send_flag(&self, heartbeat: &Heartbeat, expected_interval: f64, state: &str, hostname: &str, domain: Option<&str>, now: i64) {
+        let silent_seconds = now - heartbeat.last_timestamp;
+        let mut details = doc!(
+            "state" => state,
+            "vantage_hostname" => hostname,
+            "last_timestamp" => (heartbeat.last_timestamp),
+            "silent_seconds" => silent_seconds,
+            "expected_interval" => expected_interval
+        );
+
+        if let Some(domain) = domain {
+            details.insert("measurement_domain", domain);
+        }
+
+        let mut flag = Flag::from_measurement_id(heartbeat.last_measurement_id.clone(), &self.status, &self.name);
+        flag.details = Some(details);
+        self.flag_tx.send(flag);
+    }
+}
+
+impl Analyzer for HeartbeatAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve variables from document
+        let measurement_id = match document.get("_id") {
+            Some(&Bson::ObjectId(ref measurement_id)) => measurement_id.clone(),
+            _ => return Ok(()),
+        };
+
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let timestamp = match document.get("timestamp") {
+            Some(&Bson::I64(timestamp)) => timestamp,
+            _ => return Ok(()),
+        };
+
+        //a measurement from a quiet series clears it
+        let mut cleared = Vec::new();
+        {
+            let vantage = self.vantages.entry(hostname.clone()).or_insert(Heartbeat::new(timestamp, measurement_id.clone()));
+            vantage.touch(timestamp, measurement_id.clone());
+            if vantage.quiet {
+                vantage.quiet = false;
+                cleared.push(None);
+            }
+        }
+
+        {
+            let heartbeat = self.domains.entry(hostname.clone()).or_insert(HashMap::new())
+                .entry(domain.clone()).or_insert(Heartbeat::new(timestamp, measurement_id.clone()));
+            heartbeat.update(timestamp, measurement_id);
+            if heartbeat.quiet {
+                heartbeat.quiet = false;
+                cleared.push(Some(domain.clone()));
+            }
+        }
+
+        for domain in cleared {
+            let (heartbeat, expected_interval) = match domain {
+                Some(ref domain) => {
+                    let heartbeat = &self.domains[&hostname][domain];
+                    (heartbeat, heartbeat.expected_interval(self.expected_interval_seconds, self.minimum_gaps))
+                },
+                None => (&self.vantages[&hostname], self.vantage_interval(&hostname)),
+            };
+
+            self.send_flag(heartbeat, expected_interval, "cleared", &hostname, domain.as_ref().map(|x| x.as_str()), timestamp);
+        }
+
+        Ok(())
+    }
+
+    fn tick(&mut self, now: i64) -> Result<(), TipupError> {
+        let vantage_intervals: HashMap<String, f64> = self.vantages.keys()
+            .map(|x| (x.to_owned(), self.vantage_interval(x)))
+            .collect();
+
+        //find vantages and domains that have gone quiet
+        let mut raised = Vec::new();
+        for (hostname, vantage) in self.vantages.iter_mut() {
+            let limit = self.tolerance * vantage_intervals[hostname];
+            let vantage_quiet = (now - vantage.last_timestamp) as f64 > limit;
+            if vantage_quiet && !vantage.quiet {
+                vantage.quiet = true;
+                raised.push((hostname.to_owned(), None));
+            }
+
+            //a quiet vantage already covers its domains
+            if vantage_quiet {
+                continue;
+            }
+
+            if let Some(domains) = self.domains.get_mut(hostname) {
+                for (domain, heartbeat) in domains.iter_mut() {
+                    let limit = self.tolerance * heartbeat.expected_interval(self.expected_interval_seconds, self.minimum_gaps);
+                    if (now - heartbeat.last_timestamp) as f64 > limit && !heartbeat.quiet {
+                        heartbeat.quiet = true;
+                        raised.push((hostname.to_owned(), Some(domain.to_owned())));
+                    }
+                }
+            }
+        }
+
+        for (hostname, domain) in raised {
+            let (heartbeat, expected_interval) = match domain {
+                Some(ref domain) => {
+                    let heartbeat = &self.domains[&hostname][domain];
+                    (heartbeat, heartbeat.expected_interval(self.expected_interval_seconds, self.minimum_gaps))
+                },
+                None => (&self.vantages[&hostname], vantage_intervals[&hostname]),
+            };
+
+            self.send_flag(heartbeat, expected_interval, "raised", &hostname, domain.as_ref().map(|x| x.as_str()), now);
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Option<Document>, TipupError> {
+        let mut entries = Vec::new();
+        for (hostname, vantage) in self.vantages.iter() {
+            entries.push(HeartbeatEntry {
+                vantage_hostname: hostname.to_owned(),
+                measurement_domain: None,
+                heartbeat: vantage.clone(),
+            });
+        }
+
+        for (hostname, domains) in self.domains.iter() {
+            for (domain, heartbeat) in domains.iter() {
+                entries.push(HeartbeatEntry {
+                    vantage_hostname: hostname.to_owned(),
+                    measurement_domain: Some(domain.to_owned()),
+                    heartbeat: heartbeat.clone(),
+                });
+            }
+        }
+
+        match bson::to_bson(&entries) {
+            Ok(entries) => Ok(Some(doc!("heartbeats" => entries))),
+            Err(e) => Err(TipupError::from(format!("failed to encode heartbeats: {}", e))),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        let entries: Vec<HeartbeatEntry> = match snapshot.get("heartbeats") {
+            Some(heartbeats) => match bson::from_bson(heartbeats.clone()) {
+                Ok(entries) => entries,
+                Err(e) => return Err(TipupError::from(format!("failed to decode heartbeats: {}", e))),
+            },
+            None => return Err(TipupError::from("failed to parse 'heartbeats' from heartbeat snapshot")),
+        };
+
+        for entry in entries {
+            match entry.measurement_domain {
+                Some(domain) => {
+                    self.domains.entry(entry.vantage_hostname).or_insert(HashMap::new()).insert(domain, entry.heartbeat);
+                },
+                None => {
+                    self.vantages.insert(entry.vantage_hostname, entry.heartbeat);
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::oid::ObjectId;
+    use chan;
+
+    use analyzer::Analyzer;
+    use flag_manager::Flag;
+    use super::{HeartbeatAnalyzer, HeartbeatParameters};
+
+    #[test]
+    fn vantage_interval_from_domain_rounds() {
+        let parameters = HeartbeatParameters {
+            expected_interval_seconds: Some(60.0),
+            ..Default::default()
+        };
+
+        let (flag_tx, flag_rx) = chan::async();
+        let mut analyzer = HeartbeatAnalyzer::new("heartbeat", "silent", &parameters, flag_tx).unwrap();
+
+        //hourly rounds measuring three domains ten seconds apart
+        let mut last_timestamp = 0;
+        for round in 0..5 {
+            for (i, domain) in ["a.com", "b.com", "c.com"].iter().enumerate() {
+                last_timestamp = (round * 3600) + (i as i64 * 10);
+                let document = doc!(
+                    "_id" => (ObjectId::new().unwrap()),
+                    "vantage_hostname" => "vantage",
+                    "measurement_domain" => (*domain),
+                    "timestamp" => last_timestamp
+                );
+
+                analyzer.process_measurement(&document).unwrap();
+            }
+        }
+
+        assert_eq!(analyzer.vantage_interval("vantage"), 3600.0);
+
+        //a vantage within its rounds is not silent, one missing several rounds is
+        analyzer.tick(last_timestamp + (2 * 3600)).unwrap();
+        analyzer.tick(last_timestamp + (4 * 3600)).unwrap();
+
+        drop(analyzer);
+        let flags: Vec<Flag> = flag_rx.iter().collect();
+        assert_eq!(flags.len(), 1);
+
+        let details = flags[0].details.as_ref().unwrap();
+        assert_eq!(details.get_str("state").unwrap(), "raised");
+        assert!(details.get("measurement_domain").is_none());
+    }
+}
@@ -0,0 +1,181 @@
+use bson::Bson;
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+use mongodb::db::Database;
+
+use analyzer::{self, Analyzer, Direction};
+use error::TipupError;
+use flag_manager::Flag;
+use result_window::{self, ResultWindow, VariableWindow};
+
+use std::sync::{Arc, RwLock};
+
+//scales the median absolute deviation to estimate the standard deviation of normal data
+const MAD_SCALE: f64 = 1.4826;
+
+//flags values more than k scaled median absolute deviations from the windowed median, so a
+//single outlier in the window barely moves the baseline
+pub struct MadAnalyzer {
+    name: String,
+    status: String,
+    variable_name: Vec<String>,
+    k: f64,
+    minimum_samples: usize,
+    window_length: usize,
+    direction: Direction,
+    variable_window: Arc<RwLock<VariableWindow>>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct MadParameters {
+    pub variable_name: Option<Vec<String>>,
+    pub k: Option<f64>,
+    pub minimum_samples: Option<usize>,
+    pub window_length: Option<usize>,
+    pub direction: Option<String>,
+    pub history_days: Option<usize>,
+}
+
+impl MadAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &MadParameters, result_window: Arc<RwLock<ResultWindow>>, flag_tx: Sender<Flag>) -> Result<MadAnalyzer, TipupError> {
+        //parse parameters
+        let variable_name = try!(analyzer::parse_variable_name(&parameters.variable_name, name));
+        let k = try!(analyzer::parse_positive_f64(parameters.k, "k", 3.5, name));
+        let minimum_samples = try!(analyzer::parse_usize(parameters.minimum_samples, "minimum_samples", 5, 3, name));
+        let window_length = try!(analyzer::parse_usize(parameters.window_length, "window_length", 10, 3, name));
+        if minimum_samples > window_length {
+            return Err(TipupError::from(format!("'minimum_samples' parameter exceeds 'window_length' in analyzer '{}'", name)));
+        }
+
+        let direction = try!(analyzer::parse_direction(&parameters.direction, name));
+        let history_days = try!(analyzer::parse_usize(parameters.history_days, "history_days", 5, 1, name));
+
+        let variable_window;
+        {
+            let mut result_window = result_window.write().unwrap();
+            variable_window = try!(result_window.register_variable(name, &variable_name, window_length, history_days as i64 * 86400));
+        }
+
+        Ok(
+            MadAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                variable_name: variable_name,
+                k: k,
+                minimum_samples: minimum_samples,
+                window_length: window_length,
+                direction: direction,
+                variable_window: variable_window,
+                flag_tx: flag_tx,
+            }
+        )
+    }
+}
+
+impl Analyzer for MadAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve variables from document
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let value = match result_window::get_value(&self.variable_name, document) {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+
+        {
+            //get list of values from result window
+            let variable_window = self.variable_window.read().unwrap();
+            let values: &Vec<f64> = match variable_window.get_values(&hostname, &domain) {
+                Some(values) => values,
+                None => return Ok(()),
+            };
+
+            //only consider the most recent window_length values
+            let start = if values.len() > self.window_length { values.len() - self.window_length } else { 0 };
+            let mut values = values[start..].to_vec();
+            if values.len() < self.minimum_samples {
+                return Ok(());
+            }
+
+            //compute median and median absolute deviation
+            let median = analyzer::compute_median(&mut values);
+            let mut deviations: Vec<f64> = values.iter().map(|v| (*v - median).abs()).collect();
+            let mad = analyzer::compute_median(&mut deviations);
+
+            //if value is further than k scaled deviations raise warning
+            let z_score = (value - median) / (MAD_SCALE * mad).max(analyzer::deviation_floor(median));
+            if self.direction.exceeds(z_score, self.k) {
+                let samples = values.len() as i64;
+                let details = doc!(
+                    "value" => value,
+                    "median" => median,
+                    "mad" => mad,
+                    "z_score" => z_score,
+                    "samples" => samples
+                );
+
+                let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
+                self.flag_tx.send(flag);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn initialize(&mut self, db: &Database) -> Result<(), TipupError> {
+        let mut variable_window = self.variable_window.write().unwrap();
+        variable_window.initialize(db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::oid::ObjectId;
+    use chan;
+
+    use analyzer::Analyzer;
+    use flag_manager::Flag;
+    use result_window::ResultWindow;
+    use super::{MadAnalyzer, MadParameters};
+
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn zero_mad_jump() {
+        let parameters = MadParameters {
+            variable_name: Some(vec!("total_time".to_owned())),
+            ..Default::default()
+        };
+
+        let result_window = Arc::new(RwLock::new(ResultWindow::new()));
+        let (flag_tx, flag_rx) = chan::async();
+        let mut analyzer = MadAnalyzer::new("mad", "latency", &parameters, result_window.clone(), flag_tx).unwrap();
+
+        //a constant baseline followed by a jump
+        for value in &[100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 150.0] {
+            let document = doc!(
+                "_id" => (ObjectId::new().unwrap()),
+                "vantage_hostname" => "vantage",
+                "measurement_domain" => "example.com",
+                "total_time" => (*value)
+            );
+
+            analyzer.process_measurement(&document).unwrap();
+            result_window.read().unwrap().add_result("mad", &document).unwrap();
+        }
+
+        drop(analyzer);
+        let flags: Vec<Flag> = flag_rx.iter().collect();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].details.as_ref().unwrap().get_f64("value").unwrap(), 150.0);
+    }
+}
@@ -1,13 +1,160 @@
+use bson::{Bson, Document};
 use bson::ordered::OrderedDocument;
+use mongodb::db::Database;
 
+pub mod blockpage_analyzer;
+pub mod certificate_analyzer;
+pub mod change_point_analyzer;
+pub mod composite_analyzer;
+pub mod consensus_analyzer;
+pub mod content_drift_analyzer;
+pub mod dns_answer_analyzer;
 pub mod error_analyzer;
-pub mod std_dev_analyzer; 
+pub mod failure_rate_analyzer;
+pub mod heartbeat_analyzer;
+pub mod mad_analyzer;
+pub mod rule_analyzer;
+pub mod script_analyzer;
+pub mod seasonal_analyzer;
+pub mod std_dev_analyzer;
 
+pub use analyzer::blockpage_analyzer::{BlockpageAnalyzer, BlockpageParameters};
+pub use analyzer::certificate_analyzer::{CertificateAnalyzer, CertificateParameters};
+pub use analyzer::change_point_analyzer::{ChangePointAnalyzer, ChangePointParameters};
+pub use analyzer::composite_analyzer::{CompositeAnalyzer, CompositeChild, CompositeParameters};
+pub use analyzer::consensus_analyzer::{ConsensusAnalyzer, ConsensusParameters};
+pub use analyzer::content_drift_analyzer::{ContentDriftAnalyzer, ContentDriftParameters};
+pub use analyzer::dns_answer_analyzer::{DnsAnswerAnalyzer, DnsAnswerParameters};
 pub use analyzer::error_analyzer::ErrorAnalyzer;
-pub use analyzer::std_dev_analyzer::StdDevAnalyzer;
+pub use analyzer::failure_rate_analyzer::{FailureRateAnalyzer, FailureRateParameters};
+pub use analyzer::heartbeat_analyzer::{HeartbeatAnalyzer, HeartbeatParameters};
+pub use analyzer::mad_analyzer::{MadAnalyzer, MadParameters};
+pub use analyzer::rule_analyzer::{RuleAnalyzer, RuleParameters};
+pub use analyzer::script_analyzer::{ScriptAnalyzer, ScriptParameters};
+pub use analyzer::seasonal_analyzer::{SeasonalAnalyzer, SeasonalParameters};
+pub use analyzer::std_dev_analyzer::{StdDevAnalyzer, StdDevParameters};
 
 use error::TipupError;
 
-pub trait Analyzer {
+use std::cmp::Ordering;
+
+pub trait Analyzer: Send {
     fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError>;
+
+    //called once before measurements are processed, after any snapshot was restored, to warm up
+    //state the snapshot does not hold
+    fn initialize(&mut self, _db: &Database) -> Result<(), TipupError> {
+        Ok(())
+    }
+
+    //called periodically with the unix timestamp up to which measurements have been ingested, for
+    //analyzers that act on time passing
+    fn tick(&mut self, _now: i64) -> Result<(), TipupError> {
+        Ok(())
+    }
+
+    //called before shutdown to emit any pending flags
+    fn flush(&mut self) -> Result<(), TipupError> {
+        Ok(())
+    }
+
+    //returns analyzer state to persist, None if the analyzer is stateless. mongodb keys can't
+    //contain '.', so state keyed by domain is stored as arrays of entries rather than documents
+    fn snapshot(&self) -> Result<Option<Document>, TipupError> {
+        Ok(None)
+    }
+
+    //reloads state previously returned by snapshot
+    fn restore(&mut self, _snapshot: &Document) -> Result<(), TipupError> {
+        Ok(())
+    }
+}
+
+pub enum Direction {
+    Upper,
+    Lower,
+    Both,
+}
+
+impl Direction {
+    pub fn exceeds(&self, score: f64, threshold: f64) -> bool {
+        match *self {
+            Direction::Upper => score > threshold,
+            Direction::Lower => score < -threshold,
+            Direction::Both => score.abs() > threshold,
+        }
+    }
+}
+
+pub fn parse_variable_name(variable_name: &Option<Vec<String>>, name: &str) -> Result<Vec<String>, TipupError> {
+    match *variable_name {
+        Some(ref variable_name) if variable_name.len() == 0 => Err(TipupError::from(format!("'variable_name' parameter is empty in analyzer '{}'", name))),
+        Some(ref variable_name) => Ok(variable_name.to_owned()),
+        None => Err(TipupError::from(format!("missing 'variable_name' parameter in analyzer '{}'", name))),
+    }
+}
+
+pub fn parse_path(path: &Option<Vec<String>>, key: &str, default: &[&str], name: &str) -> Result<Vec<String>, TipupError> {
+    match *path {
+        Some(ref path) if path.len() == 0 => Err(TipupError::from(format!("'{}' parameter is empty in analyzer '{}'", key, name))),
+        Some(ref path) => Ok(path.to_owned()),
+        None => Ok(default.iter().map(|x| x.to_string()).collect()),
+    }
+}
+
+pub fn parse_string_array(document: &OrderedDocument, key: &str, name: &str) -> Result<Option<Vec<String>>, TipupError> {
+    match document.get(key) {
+        Some(&Bson::Array(ref array)) => {
+            let mut strings = Vec::new();
+            for x in array {
+                match x {
+                    &Bson::String(ref y) => strings.push(y.to_owned()),
+                    _ => return Err(TipupError::from(format!("failed to parse '{}' element as String in '{}'", key, name))),
+                }
+            }
+
+            Ok(Some(strings))
+        },
+        None => Ok(None),
+        _ => Err(TipupError::from(format!("failed to parse '{}' as Array in '{}'", key, name))),
+    }
+}
+
+pub fn parse_positive_f64(value: Option<f64>, key: &str, default: f64, name: &str) -> Result<f64, TipupError> {
+    match value {
+        Some(f) if f > 0.0 => Ok(f),
+        None => Ok(default),
+        _ => Err(TipupError::from(format!("'{}' parameter must be a positive number in analyzer '{}'", key, name))),
+    }
+}
+
+pub fn parse_usize(value: Option<usize>, key: &str, default: usize, minimum: usize, name: &str) -> Result<usize, TipupError> {
+    match value {
+        Some(i) if i >= minimum => Ok(i),
+        None => Ok(default),
+        _ => Err(TipupError::from(format!("'{}' parameter must be an integer >= {} in analyzer '{}'", key, minimum, name))),
+    }
+}
+
+pub fn parse_direction(direction: &Option<String>, name: &str) -> Result<Direction, TipupError> {
+    match direction.as_ref().map(|x| x.as_str()) {
+        Some("upper") | None => Ok(Direction::Upper),
+        Some("lower") => Ok(Direction::Lower),
+        Some("both") => Ok(Direction::Both),
+        _ => Err(TipupError::from(format!("'direction' parameter must be one of 'upper', 'lower' or 'both' in analyzer '{}'", name))),
+    }
+}
+
+//smallest deviation values are scored against, so a jump from a constant baseline still flags
+pub fn deviation_floor(center: f64) -> f64 {
+    (center.abs() * 1e-3).max(1e-9)
+}
+
+pub fn compute_median(values: &mut Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let middle = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[middle - 1] + values[middle]) / 2.0,
+        _ => values[middle],
+    }
 }
@@ -0,0 +1,54 @@
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+
+use analyzer::Analyzer;
+use error::TipupError;
+use flag_manager::Flag;
+use rule::Rule;
+
+//flags measurements matching a rule expression (see rule.rs for the grammar)
+pub struct RuleAnalyzer {
+    name: String,
+    status: String,
+    rule: Rule,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct RuleParameters {
+    pub expression: Option<String>,
+}
+
+impl RuleAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &RuleParameters, flag_tx: Sender<Flag>) -> Result<RuleAnalyzer, TipupError> {
+        //parse parameters
+        let rule = match parameters.expression {
+            Some(ref expression) => match Rule::parse(expression) {
+                Ok(rule) => rule,
+                Err(e) => return Err(TipupError::from(format!("failed to parse 'expression' parameter in analyzer '{}': {}", name, e))),
+            },
+            None => return Err(TipupError::from(format!("missing 'expression' parameter in analyzer '{}'", name))),
+        };
+
+        Ok(
+            RuleAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                rule: rule,
+                flag_tx: flag_tx,
+            }
+        )
+    }
+}
+
+impl Analyzer for RuleAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        if self.rule.evaluate(document) {
+            let details = doc!("expression" => (self.rule.expression()));
+            let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
+            self.flag_tx.send(flag);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+use bson::{Bson, Document};
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+use analyzer::{self, Analyzer};
+use error::TipupError;
+use flag_manager::Flag;
+
+//runs a sandboxed rhai script against each measurement, the script value decides whether to flag
+pub struct ScriptAnalyzer {
+    name: String,
+    status: String,
+    engine: Engine,
+    ast: AST,
+    state: Map,
+    max_map_size: usize,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct ScriptParameters {
+    pub script: Option<String>,
+    pub max_operations: Option<usize>,
+    pub max_string_size: Option<usize>,
+    pub max_array_size: Option<usize>,
+    pub max_map_size: Option<usize>,
+}
+
+impl ScriptAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &ScriptParameters, flag_tx: Sender<Flag>) -> Result<ScriptAnalyzer, TipupError> {
+        //parse parameters
+        let script = match parameters.script {
+            Some(ref script) => script.to_owned(),
+            None => return Err(TipupError::from(format!("missing 'script' parameter in analyzer '{}'", name))),
+        };
+
+        let max_operations = try!(analyzer::parse_usize(parameters.max_operations, "max_operations", 100000, 1, name));
+        let max_string_size = try!(analyzer::parse_usize(parameters.max_string_size, "max_string_size", 65536, 1, name));
+        let max_array_size = try!(analyzer::parse_usize(parameters.max_array_size, "max_array_size", 10000, 1, name));
+        let max_map_size = try!(analyzer::parse_usize(parameters.max_map_size, "max_map_size", 10000, 1, name));
+
+        //create sandboxed engine
+        let mut engine = Engine::new();
+        engine.set_max_operations(max_operations as u64);
+        engine.set_max_string_size(max_string_size);
+        engine.set_max_array_size(max_array_size);
+        engine.set_max_map_size(max_map_size);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+
+        let (print_name, debug_name) = (name.to_owned(), name.to_owned());
+        engine.on_print(move |message| info!("script analyzer '{}': {}", print_name, message));
+        engine.on_debug(move |message, _, _| debug!("script analyzer '{}': {}", debug_name, message));
+
+        let ast = match engine.compile(&script) {
+            Ok(ast) => ast,
+            Err(e) => return Err(TipupError::from(format!("failed to compile script in analyzer '{}': {}", name, e))),
+        };
+
+        Ok(
+            ScriptAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                engine: engine,
+                ast: ast,
+                state: Map::new(),
+                max_map_size: max_map_size,
+                flag_tx: flag_tx,
+            }
+        )
+    }
+}
+
+impl Analyzer for ScriptAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //run script with measurement and state in scope
+        let mut scope = Scope::new();
+        scope.push("measurement", document_to_map(document));
+        scope.push("state", self.state.clone());
+
+        let result = self.engine.eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast);
+
+        //keep state even when the script fails part way through
+        if let Some(state) = scope.get_value::<Map>("state") {
+            //nested maps and arrays count against the limit so state can't grow through nesting
+            if map_size(&state) > self.max_map_size {
+                return Err(TipupError::from(format!("script state exceeds {} entries in analyzer '{}'", self.max_map_size, self.name)));
+            }
+
+            self.state = state;
+        }
+
+        let result = try!(result);
+
+        //interpret result as a flag decision
+        let (status, details) = if result.is_unit() {
+            return Ok(());
+        } else if let Ok(flag) = result.as_bool() {
+            match flag {
+                true => (self.status.clone(), None),
+                false => return Ok(()),
+            }
+        } else if result.is_string() {
+            (result.into_string().unwrap(), None)
+        } else if result.is_map() {
+            let map = result.cast::<Map>();
+            let status = match map.get("status") {
+                Some(status) if status.is_string() => status.clone().into_string().unwrap(),
+                None => self.status.clone(),
+                _ => return Err(TipupError::from(format!("script returned a non string 'status' in analyzer '{}'", self.name))),
+            };
+
+            let details = match map.get("details") {
+                Some(details) if details.is_map() => Some(map_to_document(&details.clone().cast::<Map>())),
+                None => None,
+                _ => return Err(TipupError::from(format!("script returned non map 'details' in analyzer '{}'", self.name))),
+            };
+
+            (status, details)
+        } else {
+            return Err(TipupError::from(format!("script returned unsupported type '{}' in analyzer '{}'", result.type_name(), self.name)));
+        };
+
+        let mut flag = try!(Flag::new(document, &status, &self.name));
+        flag.details = details;
+        self.flag_tx.send(flag);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Option<Document>, TipupError> {
+        Ok(Some(doc!("state" => (map_to_entries(&self.state)))))
+    }
+
+    fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        match snapshot.get("state") {
+            Some(&Bson::Array(ref entries)) => {
+                self.state = try!(entries_to_map(entries));
+                Ok(())
+            },
+            //snapshots written before state was stored as entries
+            Some(&Bson::Document(ref state)) => {
+                self.state = document_to_map(state);
+                Ok(())
+            },
+            _ => Err(TipupError::from(format!("failed to parse 'state' from snapshot in analyzer '{}'", self.name))),
+        }
+    }
+}
+
+//number of entries in a map, including those of nested maps and arrays
+fn map_size(map: &Map) -> usize {
+    map.values().fold(map.len(), |size, value| size + value_size(value))
+}
+
+fn value_size(value: &Dynamic) -> usize {
+    if value.is_map() {
+        map_size(&value.clone().cast::<Map>())
+    } else if value.is_array() {
+        let array = value.clone().cast::<Array>();
+        array.iter().fold(array.len(), |size, value| size + value_size(value))
+    } else {
+        0
+    }
+}
+
+//script state keys are arbitrary (ex. domains), so maps are stored as arrays of key and value
+//entries and nested maps as {"entries": [..]} documents
+fn map_to_entries(map: &Map) -> Vec<Bson> {
+    map.iter().map(|(key, value)| Bson::Document(doc!(
+        "key" => (key.to_string()),
+        "value" => (state_to_bson(value))
+    ))).collect()
+}
+
+fn state_to_bson(value: &Dynamic) -> Bson {
+    if value.is_map() {
+        Bson::Document(doc!("entries" => (map_to_entries(&value.clone().cast::<Map>()))))
+    } else if value.is_array() {
+        let array = value.clone().cast::<Array>();
+        Bson::Array(array.iter().map(state_to_bson).collect())
+    } else {
+        dynamic_to_bson(value)
+    }
+}
+
+fn entries_to_map(entries: &Vec<Bson>) -> Result<Map, TipupError> {
+    let mut map = Map::new();
+    for entry in entries {
+        match *entry {
+            Bson::Document(ref entry) => match (entry.get("key"), entry.get("value")) {
+                (Some(&Bson::String(ref key)), Some(value)) => { map.insert(key.as_str().into(), try!(bson_to_state(value))); },
+                _ => return Err(TipupError::from("failed to parse script state entry")),
+            },
+            _ => return Err(TipupError::from("failed to parse script state entry")),
+        }
+    }
+
+    Ok(map)
+}
+
+fn bson_to_state(value: &Bson) -> Result<Dynamic, TipupError> {
+    match *value {
+        Bson::Document(ref document) => match document.get("entries") {
+            Some(&Bson::Array(ref entries)) => Ok(Dynamic::from(try!(entries_to_map(entries)))),
+            _ => Err(TipupError::from("failed to parse script state map")),
+        },
+        Bson::Array(ref array) => {
+            let mut values = Array::new();
+            for value in array {
+                values.push(try!(bson_to_state(value)));
+            }
+
+            Ok(Dynamic::from(values))
+        },
+        ref value => Ok(bson_to_dynamic(value)),
+    }
+}
+
+fn document_to_map(document: &OrderedDocument) -> Map {
+    let mut map = Map::new();
+    for (key, value) in document.iter() {
+        map.insert(key.as_str().into(), bson_to_dynamic(value));
+    }
+
+    map
+}
+
+fn bson_to_dynamic(value: &Bson) -> Dynamic {
+    match *value {
+        Bson::FloatingPoint(f) => Dynamic::from(f),
+        Bson::I32(i) => Dynamic::from(i as i64),
+        Bson::I64(i) => Dynamic::from(i),
+        Bson::String(ref s) => Dynamic::from(s.to_owned()),
+        Bson::Boolean(b) => Dynamic::from(b),
+        Bson::Null => Dynamic::UNIT,
+        Bson::Array(ref array) => {
+            let array: Array = array.iter().map(bson_to_dynamic).collect();
+            Dynamic::from(array)
+        },
+        Bson::Document(ref document) => Dynamic::from(document_to_map(document)),
+        Bson::ObjectId(ref id) => Dynamic::from(id.to_hex()),
+        Bson::UtcDatetime(ref datetime) => Dynamic::from(datetime.timestamp()),
+        Bson::TimeStamp(i) => Dynamic::from(i),
+        ref other => Dynamic::from(other.to_string()),
+    }
+}
+
+fn map_to_document(map: &Map) -> Document {
+    let mut document = Document::new();
+    for (key, value) in map.iter() {
+        document.insert(key.to_string(), dynamic_to_bson(value));
+    }
+
+    document
+}
+
+fn dynamic_to_bson(value: &Dynamic) -> Bson {
+    if value.is_unit() {
+        Bson::Null
+    } else if let Ok(i) = value.as_int() {
+        Bson::I64(i)
+    } else if let Ok(f) = value.as_float() {
+        Bson::FloatingPoint(f)
+    } else if let Ok(b) = value.as_bool() {
+        Bson::Boolean(b)
+    } else if value.is_string() {
+        Bson::String(value.clone().into_string().unwrap())
+    } else if value.is_array() {
+        let array = value.clone().cast::<Array>();
+        Bson::Array(array.iter().map(dynamic_to_bson).collect())
+    } else if value.is_map() {
+        Bson::Document(map_to_document(&value.clone().cast::<Map>()))
+    } else {
+        Bson::String(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, Document};
+    use chan;
+
+    use analyzer::Analyzer;
+    use super::{ScriptAnalyzer, ScriptParameters};
+
+    fn script_analyzer(script: &str, max_map_size: usize) -> ScriptAnalyzer {
+        let parameters = ScriptParameters {
+            script: Some(script.to_owned()),
+            max_map_size: Some(max_map_size),
+            ..Default::default()
+        };
+
+        let (flag_tx, _flag_rx) = chan::async();
+        ScriptAnalyzer::new("script", "script", &parameters, flag_tx).unwrap()
+    }
+
+    fn has_dotted_key(document: &Document) -> bool {
+        document.iter().any(|(key, value)| key.contains('.') || has_dotted_value(value))
+    }
+
+    fn has_dotted_value(value: &Bson) -> bool {
+        match *value {
+            Bson::Document(ref document) => has_dotted_key(document),
+            Bson::Array(ref array) => array.iter().any(has_dotted_value),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn snapshot_domain_keys() {
+        let script = "let domain = measurement.measurement_domain; \
+            if !(domain in state) { state[domain] = #{count: 0, hosts: []}; } \
+            state[domain].count += 1; state[domain].hosts.push(\"a.b\"); false";
+        let mut analyzer = script_analyzer(script, 100);
+        analyzer.process_measurement(&doc!("measurement_domain" => "example.com")).unwrap();
+        analyzer.process_measurement(&doc!("measurement_domain" => "example.com")).unwrap();
+
+        let snapshot = analyzer.snapshot().unwrap().unwrap();
+        assert!(!has_dotted_key(&snapshot));
+
+        let mut restored = script_analyzer(script, 100);
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot().unwrap().unwrap(), snapshot);
+        let domain = restored.state.get("example.com").unwrap().clone().cast::<::rhai::Map>();
+        assert_eq!(domain.get("count").unwrap().as_int().unwrap(), 2);
+    }
+
+    #[test]
+    fn nested_state_limit() {
+        let script = "if !(\"hosts\" in state) { state.hosts = []; } state.hosts.push(1); false";
+        let mut analyzer = script_analyzer(script, 4);
+        for _ in 0..3 {
+            analyzer.process_measurement(&doc!("measurement_domain" => "example.com")).unwrap();
+        }
+
+        assert!(analyzer.process_measurement(&doc!("measurement_domain" => "example.com")).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use bson::Bson;
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+use mongodb::db::Database;
+
+use analyzer::{self, Analyzer, Direction};
+use error::TipupError;
+use flag_manager::Flag;
+use result_window::{self, ResultWindow, SeasonalWindow};
+
+use std::sync::{Arc, RwLock};
+
+//compares values against the same hour of the week for each vantage and domain, so diurnal
+//and weekly patterns are not flagged
+pub struct SeasonalAnalyzer {
+    name: String,
+    status: String,
+    variable_name: Vec<String>,
+    k: f64,
+    minimum_samples: usize,
+    direction: Direction,
+    seasonal_window: Arc<RwLock<SeasonalWindow>>,
+    flag_tx: Sender<Flag>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct SeasonalParameters {
+    pub variable_name: Option<Vec<String>>,
+    pub k: Option<f64>,
+    pub minimum_samples: Option<usize>,
+    pub history_days: Option<usize>,
+    pub bucket_length: Option<usize>,
+    pub direction: Option<String>,
+}
+
+impl SeasonalAnalyzer {
+    pub fn new(name: &str, status: &str, parameters: &SeasonalParameters, result_window: Arc<RwLock<ResultWindow>>, flag_tx: Sender<Flag>) -> Result<SeasonalAnalyzer, TipupError> {
+        //parse parameters
+        let variable_name = try!(analyzer::parse_variable_name(&parameters.variable_name, name));
+        let k = try!(analyzer::parse_positive_f64(parameters.k, "k", 3.0, name));
+        let minimum_samples = try!(analyzer::parse_usize(parameters.minimum_samples, "minimum_samples", 4, 2, name));
+        let history_days = try!(analyzer::parse_usize(parameters.history_days, "history_days", 28, 1, name));
+        let bucket_length = try!(analyzer::parse_usize(parameters.bucket_length, "bucket_length", 20, 2, name));
+        if minimum_samples > bucket_length {
+            return Err(TipupError::from(format!("'minimum_samples' parameter exceeds 'bucket_length' in analyzer '{}'", name)));
+        }
+
+        let direction = try!(analyzer::parse_direction(&parameters.direction, name));
+
+        let seasonal_window;
+        {
+            let mut result_window = result_window.write().unwrap();
+            seasonal_window = try!(result_window.register_seasonal(name, &variable_name, history_days as i64 * 86400, bucket_length));
+        }
+
+        Ok(
+            SeasonalAnalyzer {
+                name: name.to_owned(),
+                status: status.to_owned(),
+                variable_name: variable_name,
+                k: k,
+                minimum_samples: minimum_samples,
+                direction: direction,
+                seasonal_window: seasonal_window,
+                flag_tx: flag_tx,
+            }
+        )
+    }
+}
+
+impl Analyzer for SeasonalAnalyzer {
+    fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
+        //retrieve variables from document
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
+            _ => return Ok(()),
+        };
+
+        let timestamp = match document.get("timestamp") {
+            Some(&Bson::I64(timestamp)) => timestamp,
+            _ => return Ok(()),
+        };
+
+        let value = match result_window::get_value(&self.variable_name, document) {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+
+        {
+            //get buckets from seasonal window
+            let seasonal_window = self.seasonal_window.read().unwrap();
+            let buckets = match seasonal_window.get_buckets(&hostname, &domain) {
+                Some(buckets) => buckets,
+                None => return Ok(()),
+            };
+
+            //use the hour of week bucket, falling back to the hour of day across the week
+            let hour_of_week = result_window::hour_of_week(timestamp);
+            let (values, bucket_type) = if buckets[hour_of_week].len() >= self.minimum_samples {
+                (buckets[hour_of_week].clone(), "hour_of_week")
+            } else {
+                let mut values = Vec::new();
+                for day in 0..7 {
+                    values.extend_from_slice(&buckets[(day * 24) + (hour_of_week % 24)]);
+                }
+
+                (values, "hour_of_day")
+            };
+
+            if values.len() < self.minimum_samples {
+                return Ok(());
+            }
+
+            //compute mean and sample standard deviation of bucket
+            let mut mean = 0.0;
+            for v in values.iter() {
+                mean += *v;
+            }
+            mean /= values.len() as f64;
+
+            let mut sum_squares = 0.0;
+            for v in values.iter() {
+                sum_squares += (*v - mean).powf(2.0);
+            }
+
+            let std_dev = (sum_squares / (values.len() - 1) as f64).sqrt();
+
+            //if value is further than k standard deviations raise warning
+            let z_score = (value - mean) / std_dev.max(analyzer::deviation_floor(mean));
+            if self.direction.exceeds(z_score, self.k) {
+                let (day_of_week, hour_of_day) = ((hour_of_week / 24) as i32, (hour_of_week % 24) as i32);
+                let samples = values.len() as i64;
+                let details = doc!(
+                    "value" => value,
+                    "bucket" => bucket_type,
+                    "day_of_week" => day_of_week,
+                    "hour_of_day" => hour_of_day,
+                    "mean" => mean,
+                    "std_dev" => std_dev,
+                    "z_score" => z_score,
+                    "samples" => samples
+                );
+
+                let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
+                self.flag_tx.send(flag);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn initialize(&mut self, db: &Database) -> Result<(), TipupError> {
+        let mut seasonal_window = self.seasonal_window.write().unwrap();
+        seasonal_window.initialize(db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::oid::ObjectId;
+    use chan;
+
+    use analyzer::Analyzer;
+    use flag_manager::Flag;
+    use result_window::ResultWindow;
+    use super::{SeasonalAnalyzer, SeasonalParameters};
+
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn constant_bucket_jump() {
+        let parameters = SeasonalParameters {
+            variable_name: Some(vec!("total_time".to_owned())),
+            ..Default::default()
+        };
+
+        let result_window = Arc::new(RwLock::new(ResultWindow::new()));
+        let (flag_tx, flag_rx) = chan::async();
+        let mut analyzer = SeasonalAnalyzer::new("seasonal", "latency", &parameters, result_window.clone(), flag_tx).unwrap();
+
+        //the same hour of week over previous weeks, then a jump
+        for (week, value) in [100.0, 100.0, 100.0, 100.0, 150.0].iter().enumerate() {
+            let document = doc!(
+                "_id" => (ObjectId::new().unwrap()),
+                "vantage_hostname" => "vantage",
+                "measurement_domain" => "example.com",
+                "timestamp" => (week as i64 * 7 * 24 * 3600),
+                "total_time" => (*value)
+            );
+
+            analyzer.process_measurement(&document).unwrap();
+            result_window.read().unwrap().add_result("seasonal", &document).unwrap();
+        }
+
+        drop(analyzer);
+        let flags: Vec<Flag> = flag_rx.iter().collect();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].details.as_ref().unwrap().get_str("bucket").unwrap(), "hour_of_week");
+    }
+}
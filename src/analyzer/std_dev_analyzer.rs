@@ -1,44 +1,59 @@
 use bson::Bson;
 use bson::ordered::OrderedDocument;
 use chan::Sender;
+use mongodb::db::Database;
 
-use analyzer::Analyzer;
+use analyzer::{self, Analyzer, Direction};
 use error::TipupError;
 use flag_manager::Flag;
-use result_window::{ResultWindow, VariableWindow};
+use result_window::{self, ResultWindow, VariableWindow};
 
 use std::sync::{Arc, RwLock};
 
+//flags values more than k standard deviations from the windowed mean
 pub struct StdDevAnalyzer {
     name: String,
     status: String,
     variable_name: Vec<String>,
+    k: f64,
+    minimum_samples: usize,
+    window_length: usize,
+    direction: Direction,
+    population: bool,
     variable_window: Arc<RwLock<VariableWindow>>,
     flag_tx: Sender<Flag>,
 }
 
+#[derive(Default, Deserialize, Serialize)]
+pub struct StdDevParameters {
+    pub variable_name: Option<Vec<String>>,
+    pub k: Option<f64>,
+    pub minimum_samples: Option<usize>,
+    pub window_length: Option<usize>,
+    pub direction: Option<String>,
+    pub history_days: Option<usize>,
+    pub population: Option<bool>,
+}
+
 impl StdDevAnalyzer {
-    pub fn new(name: &str, status: &str, parameters: &OrderedDocument, result_window: Arc<RwLock<ResultWindow>>, flag_tx: Sender<Flag>, ) -> Result<StdDevAnalyzer, TipupError> {
-        //parse parameters to retrieve variable name
-        let variable_name = match parameters.get("variable_name") {
-            Some(&Bson::Array(ref param_variable_name)) => {
-                let mut variable_name = Vec::new();
-                for x in param_variable_name {
-                    match x {
-                        &Bson::String(ref y) => variable_name.push(y.to_owned()),
-                        _ => return Err(TipupError::from("failed to parse variable name as String in StdDevAnalzyer")),
-                    }
-                }
-
-                variable_name
-            },
-            _ => return Err(TipupError::from("failed to parse variable name parameter in StdDevAnalyzer")),
-        };
+    pub fn new(name: &str, status: &str, parameters: &StdDevParameters, result_window: Arc<RwLock<ResultWindow>>, flag_tx: Sender<Flag>) -> Result<StdDevAnalyzer, TipupError> {
+        //parse parameters
+        let variable_name = try!(analyzer::parse_variable_name(&parameters.variable_name, name));
+        let k = try!(analyzer::parse_positive_f64(parameters.k, "k", 1.5, name));
+        let minimum_samples = try!(analyzer::parse_usize(parameters.minimum_samples, "minimum_samples", 5, 2, name));
+        let window_length = try!(analyzer::parse_usize(parameters.window_length, "window_length", 10, 2, name));
+        if minimum_samples > window_length {
+            return Err(TipupError::from(format!("'minimum_samples' parameter exceeds 'window_length' in analyzer '{}'", name)));
+        }
+
+        let direction = try!(analyzer::parse_direction(&parameters.direction, name));
+        let history_days = try!(analyzer::parse_usize(parameters.history_days, "history_days", 5, 1, name));
+        let population = parameters.population.unwrap_or(false);
 
         let variable_window;
         {
             let mut result_window = result_window.write().unwrap();
-            variable_window = try!(result_window.register_variable(&variable_name));
+            variable_window = try!(result_window.register_variable(name, &variable_name, window_length, history_days as i64 * 86400));
         }
 
         Ok(
@@ -46,6 +61,11 @@ impl StdDevAnalyzer {
                 name: name.to_owned(),
                 status: status.to_owned(),
                 variable_name: variable_name,
+                k: k,
+                minimum_samples: minimum_samples,
+                window_length: window_length,
+                direction: direction,
+                population: population,
                 variable_window: variable_window,
                 flag_tx: flag_tx,
             }
@@ -56,17 +76,17 @@ impl StdDevAnalyzer {
 impl Analyzer for StdDevAnalyzer {
     fn process_measurement(&mut self, document: &OrderedDocument) -> Result<(), TipupError> {
         //retrieve variables from document
-        let hostname = match document.get("hostname") {
+        let hostname = match document.get("vantage_hostname") {
             Some(&Bson::String(ref hostname)) => hostname.to_owned(),
             _ => return Ok(()),
         };
 
-        let url = match document.get("url") {
-            Some(&Bson::String(ref url)) => url.to_owned(),
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref domain)) => domain.to_owned(),
             _ => return Ok(()),
         };
 
-        let value = match get_value(&self.variable_name, document) {
+        let value = match result_window::get_value(&self.variable_name, document) {
             Some(value) => value,
             None => return Ok(()),
         };
@@ -74,11 +94,18 @@ impl Analyzer for StdDevAnalyzer {
         {
             //get list of values from result window
             let variable_window = self.variable_window.read().unwrap();
-            let values: &Vec<f64> = match variable_window.get_values(&hostname, &url) {
+            let values: &Vec<f64> = match variable_window.get_values(&hostname, &domain) {
                 Some(values) => values,
                 None => return Ok(()),
             };
 
+            //only consider the most recent window_length values
+            let start = if values.len() > self.window_length { values.len() - self.window_length } else { 0 };
+            let values = &values[start..];
+            if values.len() < self.minimum_samples {
+                return Ok(());
+            }
+
             //compute standard deviation of variable
             let mut mean = 0.0;
             for v in values.iter() {
@@ -86,34 +113,82 @@ impl Analyzer for StdDevAnalyzer {
             }
             mean /= values.len() as f64;
 
-            let mut std_dev = 0.0;
+            let mut sum_squares = 0.0;
             for v in values.iter() {
-                std_dev += (*v - mean).powf(2.0);
+                sum_squares += (*v - mean).powf(2.0);
             }
-            std_dev = std_dev.sqrt();
 
-            //if value is greater than 1.5 standard deviations raise warning
-            if value > mean + (1.5 * std_dev) {
-                let flag = try!(Flag::new(document, &self.status, &self.name));
+            let degrees_of_freedom = match self.population {
+                true => values.len() as f64,
+                false => (values.len() - 1) as f64,
+            };
+            let std_dev = (sum_squares / degrees_of_freedom).sqrt();
+
+            //if value is further than k standard deviations raise warning
+            let z_score = (value - mean) / std_dev.max(analyzer::deviation_floor(mean));
+            if self.direction.exceeds(z_score, self.k) {
+                let samples = values.len() as i64;
+                let details = doc!(
+                    "value" => value,
+                    "mean" => mean,
+                    "std_dev" => std_dev,
+                    "z_score" => z_score,
+                    "samples" => samples
+                );
+
+                let flag = try!(Flag::with_details(document, &self.status, &self.name, details));
                 self.flag_tx.send(flag);
             }
         }
 
         Ok(())
     }
+
+    fn initialize(&mut self, db: &Database) -> Result<(), TipupError> {
+        let mut variable_window = self.variable_window.write().unwrap();
+        variable_window.initialize(db)
+    }
 }
 
-fn get_value(variable_name: &Vec<String>, document: &OrderedDocument) -> Option<f64> {
-    let mut index_document = document;
-    for variable in variable_name {
-        match index_document.get(variable) {
-            Some(&Bson::Document(ref document)) => index_document = document,
-            Some(&Bson::FloatingPoint(f)) => return Some(f),
-            Some(&Bson::I32(i)) => return Some(i as f64),
-            Some(&Bson::I64(i)) => return Some(i as f64),
-            _ => return None,
+#[cfg(test)]
+mod tests {
+    use bson::oid::ObjectId;
+    use chan;
+
+    use analyzer::Analyzer;
+    use flag_manager::Flag;
+    use result_window::ResultWindow;
+    use super::{StdDevAnalyzer, StdDevParameters};
+
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn zero_variance_jump() {
+        let parameters = StdDevParameters {
+            variable_name: Some(vec!("total_time".to_owned())),
+            ..Default::default()
+        };
+
+        let result_window = Arc::new(RwLock::new(ResultWindow::new()));
+        let (flag_tx, flag_rx) = chan::async();
+        let mut analyzer = StdDevAnalyzer::new("std_dev", "latency", &parameters, result_window.clone(), flag_tx).unwrap();
+
+        //a constant baseline followed by a jump
+        for value in &[100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 150.0] {
+            let document = doc!(
+                "_id" => (ObjectId::new().unwrap()),
+                "vantage_hostname" => "vantage",
+                "measurement_domain" => "example.com",
+                "total_time" => (*value)
+            );
+
+            analyzer.process_measurement(&document).unwrap();
+            result_window.read().unwrap().add_result("std_dev", &document).unwrap();
         }
-    }
 
-    None
+        drop(analyzer);
+        let flags: Vec<Flag> = flag_rx.iter().collect();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].details.as_ref().unwrap().get_f64("value").unwrap(), 150.0);
+    }
 }
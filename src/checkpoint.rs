@@ -0,0 +1,201 @@
+use bson::Bson;
+use bson::oid::ObjectId;
+use bson::ordered::OrderedDocument;
+use mongodb::coll::options::FindOneAndUpdateOptions;
+use mongodb::db::{Database, ThreadedDatabase};
+
+use error::TipupError;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+//(timestamp, _id) position of a vantage host's analyzed measurements. late arrivals within the
+//lateness allowance are found by recent ids, and measurements at or before the floor written by
+//timestamp only checkpoints are all analyzed
+pub struct Checkpoint {
+    hostname: String,
+    timestamp: i64,
+    measurement_id: Option<ObjectId>,
+    recent: Vec<(i64, ObjectId)>,
+    floor: i64,
+    lateness: i64,
+    changed: bool,
+}
+
+impl Checkpoint {
+    pub fn new(hostname: &str, lateness: i64) -> Checkpoint {
+        Checkpoint {
+            hostname: hostname.to_owned(),
+            timestamp: 0,
+            measurement_id: None,
+            recent: Vec::new(),
+            floor: 0,
+            lateness: lateness,
+            changed: false,
+        }
+    }
+
+    pub fn load(db: &Database, hostname: &str, lateness: i64) -> Result<Checkpoint, TipupError> {
+        let search_document = Some(doc!("vantage_hostname" => hostname));
+        match try!(db.collection("analyzed_measurements").find_one(search_document, None)) {
+            Some(document) => Checkpoint::from_document(&document, lateness),
+            None => Ok(Checkpoint::new(hostname, lateness)),
+        }
+    }
+
+    pub fn load_all(db: &Database, lateness: i64) -> Result<HashMap<String, Checkpoint>, TipupError> {
+        let mut checkpoints = HashMap::new();
+        let cursor = try!(db.collection("analyzed_measurements").find(None, None));
+        for document in cursor {
+            let checkpoint = try!(Checkpoint::from_document(&try!(document), lateness));
+            checkpoints.insert(checkpoint.hostname.to_owned(), checkpoint);
+        }
+
+        Ok(checkpoints)
+    }
+
+    fn from_document(document: &OrderedDocument, lateness: i64) -> Result<Checkpoint, TipupError> {
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Err(TipupError::from("failed to parse 'vantage_hostname' value in analyzed_measurements")),
+        };
+
+        let timestamp = match document.get("timestamp") {
+            Some(&Bson::I64(timestamp)) => timestamp,
+            _ => return Err(TipupError::from(format!("failed to parse 'timestamp' value in analyzed_measurements for host '{}'", hostname))),
+        };
+
+        let measurement_id = match document.get("measurement_id") {
+            Some(&Bson::ObjectId(ref measurement_id)) => Some(measurement_id.clone()),
+            Some(&Bson::Null) | None => None,
+            _ => return Err(TipupError::from(format!("failed to parse 'measurement_id' value in analyzed_measurements for host '{}'", hostname))),
+        };
+
+        //documents written before (timestamp, _id) positions only hold a timestamp
+        let floor = match document.get("floor") {
+            Some(&Bson::I64(floor)) => floor,
+            Some(_) => return Err(TipupError::from(format!("failed to parse 'floor' value in analyzed_measurements for host '{}'", hostname))),
+            None if measurement_id.is_none() => timestamp,
+            None => i64::min_value(),
+        };
+
+        let mut recent = Vec::new();
+        if let Some(&Bson::Array(ref array)) = document.get("recent") {
+            for entry in array {
+                match entry {
+                    &Bson::Document(ref entry) => match (entry.get("timestamp"), entry.get("measurement_id")) {
+                        (Some(&Bson::I64(timestamp)), Some(&Bson::ObjectId(ref measurement_id))) => recent.push((timestamp, measurement_id.clone())),
+                        _ => return Err(TipupError::from(format!("failed to parse 'recent' entry in analyzed_measurements for host '{}'", hostname))),
+                    },
+                    _ => return Err(TipupError::from(format!("failed to parse 'recent' entry in analyzed_measurements for host '{}'", hostname))),
+                }
+            }
+        }
+
+        Ok(
+            Checkpoint {
+                hostname: hostname,
+                timestamp: timestamp,
+                measurement_id: measurement_id,
+                recent: recent,
+                floor: floor,
+                lateness: lateness,
+                changed: false,
+            }
+        )
+    }
+
+    //query for measurements that may not have been analyzed, sort ascending by (timestamp, _id)
+    pub fn search_document(&self) -> OrderedDocument {
+        let gte = doc!("$gte" => (self.timestamp - self.lateness));
+        doc!(
+            "vantage_hostname" => (self.hostname.to_owned()),
+            "timestamp" => gte
+        )
+    }
+
+    pub fn contains(&self, timestamp: i64, measurement_id: &ObjectId) -> bool {
+        if compare_position(timestamp, measurement_id, self.timestamp, &self.measurement_id) == Ordering::Greater {
+            return false;
+        }
+
+        //measurements at or before the floor, or later than the allowance, are treated as analyzed
+        if timestamp <= self.floor || timestamp < self.timestamp - self.lateness {
+            return true;
+        }
+
+        self.recent.iter().any(|&(_, ref x)| x == measurement_id)
+    }
+
+    //records a measurement sent to the pipe, persisted by save
+    pub fn advance(&mut self, timestamp: i64, measurement_id: &ObjectId) {
+        if compare_position(timestamp, measurement_id, self.timestamp, &self.measurement_id) == Ordering::Greater {
+            self.timestamp = timestamp;
+            self.measurement_id = Some(measurement_id.clone());
+        }
+
+        self.recent.push((timestamp, measurement_id.clone()));
+
+        let earliest = self.timestamp - self.lateness;
+        self.recent.retain(|&(x, _)| x >= earliest);
+        self.changed = true;
+    }
+
+    //writes the position and recent ids in a single document replace, call only after the
+    //advanced measurements have been processed by the pipe
+    pub fn save(&mut self, db: &Database) -> Result<(), TipupError> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let mut recent = Vec::new();
+        for &(timestamp, ref measurement_id) in self.recent.iter() {
+            recent.push(Bson::Document(doc!(
+                "timestamp" => timestamp,
+                "measurement_id" => (measurement_id.clone())
+            )));
+        }
+
+        let measurement_id = match self.measurement_id {
+            Some(ref measurement_id) => Bson::ObjectId(measurement_id.clone()),
+            None => Bson::Null,
+        };
+
+        let search_document = doc!("vantage_hostname" => (self.hostname.to_owned()));
+        let mut checkpoint_document = doc!(
+            "vantage_hostname" => (self.hostname.to_owned()),
+            "timestamp" => (self.timestamp),
+            "measurement_id" => measurement_id,
+            "recent" => recent
+        );
+
+        //the floor is kept until it falls outside the lateness allowance
+        if self.floor >= self.timestamp - self.lateness {
+            checkpoint_document.insert("floor", self.floor);
+        }
+
+        let update_options = Some(FindOneAndUpdateOptions {
+            return_document: None,
+            max_time_ms: None,
+            projection: None,
+            sort: None,
+            upsert: Some(true),
+            write_concern: None,
+        });
+
+        try!(db.collection("analyzed_measurements").find_one_and_replace(search_document, checkpoint_document, update_options));
+        self.changed = false;
+        Ok(())
+    }
+}
+
+//orders measurements by timestamp then _id, a position without an _id follows every _id at its timestamp
+fn compare_position(timestamp: i64, measurement_id: &ObjectId, position_timestamp: i64, position_id: &Option<ObjectId>) -> Ordering {
+    match timestamp.cmp(&position_timestamp) {
+        Ordering::Equal => match *position_id {
+            Some(ref position_id) => measurement_id.bytes().cmp(&position_id.bytes()),
+            None => Ordering::Less,
+        },
+        ordering => ordering,
+    }
+}
@@ -0,0 +1,282 @@
+use bson::{self, Bson};
+use bson::ordered::OrderedDocument;
+use serde::{Deserialize, Serialize};
+
+use analyzer::{BlockpageParameters, CertificateParameters, ChangePointParameters, CompositeParameters, ConsensusParameters, ContentDriftParameters, DnsAnswerParameters, FailureRateParameters, HeartbeatParameters, MadParameters, RuleParameters, ScriptParameters, SeasonalParameters, StdDevParameters};
+use pipe::ClassMatcher;
+use rule::Rule;
+
+use std::fmt::{self, Display, Formatter};
+
+//analyzer document decoded from the 'analyzers' collection, checks spanning several
+//parameters happen when the analyzer is constructed
+pub struct AnalyzerConfig {
+    pub id: Option<Bson>,
+    pub name: String,
+    pub status: String,
+    pub state: AnalyzerState,
+    pub measurement_class: ClassMatcher,
+    pub filter: Option<Rule>,
+    pub fields: Vec<String>,
+    pub parameters: AnalyzerParameters,
+    pub source: OrderedDocument,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AnalyzerState {
+    Active,
+    Paused,
+    Retired,
+}
+
+pub enum AnalyzerParameters {
+    Blockpage(BlockpageParameters),
+    Certificate(CertificateParameters),
+    ChangePoint(ChangePointParameters),
+    Composite(CompositeParameters),
+    Consensus(ConsensusParameters),
+    ContentDrift(ContentDriftParameters),
+    DnsAnswer(DnsAnswerParameters),
+    Error,
+    FailureRate(FailureRateParameters),
+    Heartbeat(HeartbeatParameters),
+    Mad(MadParameters),
+    Rule(RuleParameters),
+    Script(ScriptParameters),
+    Seasonal(SeasonalParameters),
+    StdDev(StdDevParameters),
+}
+
+//every problem found in an analyzer document
+pub struct ConfigError {
+    pub id: Option<Bson>,
+    pub name: Option<String>,
+    pub problems: Vec<String>,
+}
+
+impl AnalyzerConfig {
+    pub fn from_document(document: &OrderedDocument) -> Result<AnalyzerConfig, ConfigError> {
+        let mut problems = Vec::new();
+        let name: Option<String> = decode_field(document, "name", true, &mut problems);
+        let class: Option<String> = decode_field(document, "class", true, &mut problems);
+
+        //'status' is the analyzer state, documents without 'flag_status' use it as the flag status
+        let flag_status: Option<String> = decode_field(document, "flag_status", false, &mut problems);
+        let status: Option<String> = decode_field(document, "status", flag_status.is_none(), &mut problems);
+        let (status, state) = match (flag_status, status) {
+            (Some(flag_status), None) => (Some(flag_status), AnalyzerState::Active),
+            (Some(flag_status), Some(status)) => match parse_analyzer_state(&status) {
+                Some(state) => (Some(flag_status), state),
+                None => {
+                    problems.push(format!("unknown status '{}', expected active, paused or retired", status));
+                    (Some(flag_status), AnalyzerState::Active)
+                },
+            },
+            (None, Some(status)) => match parse_analyzer_state(&status) {
+                Some(_) => {
+                    problems.push(format!("status '{}' requires a 'flag_status'", status));
+                    (None, AnalyzerState::Active)
+                },
+                None => (Some(status), AnalyzerState::Active),
+            },
+            (None, None) => (None, AnalyzerState::Active),
+        };
+
+        let measurement_class = match document.get("measurement_class") {
+            Some(measurement_class) => match ClassMatcher::parse(measurement_class) {
+                Ok(measurement_class) => Some(measurement_class),
+                Err(e) => {
+                    problems.push(format!("invalid 'measurement_class': {}", e));
+                    None
+                },
+            },
+            None => {
+                problems.push("missing 'measurement_class'".to_owned());
+                None
+            },
+        };
+
+        let filter = match decode_field::<String>(document, "filter", false, &mut problems) {
+            Some(filter) => match Rule::parse(&filter) {
+                Ok(filter) => Some(filter),
+                Err(e) => {
+                    problems.push(format!("invalid 'filter': {}", e));
+                    None
+                },
+            },
+            None => None,
+        };
+
+        let fields: Vec<String> = decode_field(document, "fields", false, &mut problems).unwrap_or(Vec::new());
+
+        let parameters = match document.get("parameters") {
+            Some(&Bson::Document(ref parameters)) => Some(parameters.clone()),
+            Some(&Bson::Array(ref parameters)) if parameters.len() == 0 => Some(OrderedDocument::new()),
+            Some(&Bson::Null) | None => Some(OrderedDocument::new()),
+            _ => {
+                problems.push("'parameters' must be a document".to_owned());
+                None
+            },
+        };
+
+        let parameters = match (class.as_ref(), parameters) {
+            (Some(class), Some(parameters)) => AnalyzerParameters::decode(class, &parameters, &mut problems),
+            _ => None,
+        };
+
+        //changes to state or the written health do not require rebuilding the analyzer
+        let mut source = document.clone();
+        source.remove("_id");
+        source.remove("health");
+        if source.contains_key("flag_status") {
+            source.remove("status");
+        }
+
+        match (name, class, status, measurement_class, parameters) {
+            (Some(name), Some(_), Some(status), Some(measurement_class), Some(parameters)) if problems.len() == 0 => {
+                Ok(
+                    AnalyzerConfig {
+                        id: document.get("_id").cloned(),
+                        name: name,
+                        status: status,
+                        state: state,
+                        measurement_class: measurement_class,
+                        filter: filter,
+                        fields: fields,
+                        parameters: parameters,
+                        source: source,
+                    }
+                )
+            },
+            (name, _, _, _, _) => {
+                Err(
+                    ConfigError {
+                        id: document.get("_id").cloned(),
+                        name: name,
+                        problems: problems,
+                    }
+                )
+            },
+        }
+    }
+
+    //names of the composite's children, empty for other classes
+    pub fn children(&self) -> Vec<String> {
+        match self.parameters {
+            AnalyzerParameters::Composite(ref parameters) => parameters.analyzers.clone().unwrap_or(Vec::new()),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl AnalyzerParameters {
+    fn decode(class: &str, parameters: &OrderedDocument, problems: &mut Vec<String>) -> Option<AnalyzerParameters> {
+        match class {
+            "BlockpageAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::Blockpage),
+            "CertificateAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::Certificate),
+            "ChangePointAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::ChangePoint),
+            "CompositeAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::Composite),
+            "ConsensusAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::Consensus),
+            "ContentDriftAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::ContentDrift),
+            "DnsAnswerAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::DnsAnswer),
+            "ErrorAnalyzer" => {
+                for key in parameters.keys() {
+                    problems.push(format!("unknown parameter '{}'", key));
+                }
+
+                Some(AnalyzerParameters::Error)
+            },
+            "FailureRateAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::FailureRate),
+            "HeartbeatAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::Heartbeat),
+            "MadAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::Mad),
+            "RuleAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::Rule),
+            "ScriptAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::Script),
+            "SeasonalAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::Seasonal),
+            "StdDevAnalyzer" => decode_parameters(parameters, problems).map(AnalyzerParameters::StdDev),
+            _ => {
+                problems.push(format!("unknown class '{}'", class));
+                None
+            },
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let id = format_id(&self.id);
+        match self.name {
+            Some(ref name) => write!(f, "analyzer '{}' (_id {}): {}", name, id, self.problems.join("; ")),
+            None => write!(f, "analyzer document (_id {}): {}", id, self.problems.join("; ")),
+        }
+    }
+}
+
+pub fn format_id(id: &Option<Bson>) -> String {
+    match *id {
+        Some(ref id) => format!("{}", id),
+        None => "none".to_owned(),
+    }
+}
+
+fn parse_analyzer_state(status: &str) -> Option<AnalyzerState> {
+    match status {
+        "active" => Some(AnalyzerState::Active),
+        "paused" => Some(AnalyzerState::Paused),
+        "retired" => Some(AnalyzerState::Retired),
+        _ => None,
+    }
+}
+
+fn decode_field<T: Deserialize>(document: &OrderedDocument, key: &str, required: bool, problems: &mut Vec<String>) -> Option<T> {
+    match document.get(key) {
+        Some(&Bson::Null) | None => {
+            if required {
+                problems.push(format!("missing '{}'", key));
+            }
+
+            None
+        },
+        Some(value) => match bson::from_bson(value.clone()) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                problems.push(format!("invalid '{}', expected {}", key, e));
+                None
+            },
+        },
+    }
+}
+
+//decodes each parameter on its own so every unknown or mistyped parameter is reported
+fn decode_parameters<T: Default + Deserialize + Serialize>(parameters: &OrderedDocument, problems: &mut Vec<String>) -> Option<T> {
+    //bson ignores unknown fields, so they are found from the fields the struct serializes
+    let known: Vec<String> = match bson::to_bson(&T::default()) {
+        Ok(Bson::Document(document)) => document.keys().cloned().collect(),
+        _ => Vec::new(),
+    };
+
+    let problem_count = problems.len();
+    for (key, value) in parameters.iter() {
+        if !known.contains(key) {
+            problems.push(format!("unknown parameter '{}'", key));
+            continue;
+        }
+
+        let mut document = OrderedDocument::new();
+        document.insert(key.to_owned(), value.clone());
+        if let Err(e) = bson::from_bson::<T>(Bson::Document(document)) {
+            problems.push(format!("invalid '{}' parameter, expected {}", key, e));
+        }
+    }
+
+    if problems.len() > problem_count {
+        return None;
+    }
+
+    match bson::from_bson(Bson::Document(parameters.clone())) {
+        Ok(parameters) => Some(parameters),
+        Err(e) => {
+            problems.push(format!("invalid parameters: {}", e));
+            None
+        },
+    }
+}
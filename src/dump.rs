@@ -0,0 +1,105 @@
+use bson::{self, Bson};
+use bson::ordered::OrderedDocument;
+use serde_json;
+
+use error::TipupError;
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+
+//reads concatenated bson measurements, as written by chimpanzee dump-failures, sorted by
+//(timestamp, _id) since dumps are written in no particular order
+pub fn read_measurements(filename: &str) -> Result<Vec<OrderedDocument>, TipupError> {
+    let file = match File::open(filename) {
+        Ok(file) => file,
+        Err(e) => return Err(TipupError::from(format!("failed to open measurement dump '{}': {}", filename, e))),
+    };
+
+    let file_length = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return Err(TipupError::from(format!("failed to read measurement dump '{}': {}", filename, e))),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut position = 0;
+    let mut measurements = Vec::new();
+    loop {
+        //each document starts with its little endian length, the file ends between documents
+        let mut header = [0; 4];
+        match reader.read(&mut header[..1]) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => return Err(TipupError::from(format!("failed to read measurement dump '{}': {}", filename, e))),
+        }
+
+        if let Err(e) = reader.read_exact(&mut header[1..]) {
+            return Err(TipupError::from(format!("truncated document {} in measurement dump '{}': {}", measurements.len(), filename, e)));
+        }
+
+        //the length is untrusted, so check it against the rest of the file before allocating
+        let length = header.iter().rev().fold(0, |x, y| (x << 8) | *y as usize);
+        if length < 5 || length as u64 > file_length.saturating_sub(position) {
+            return Err(TipupError::from(format!("invalid length {} of document {} in measurement dump '{}'", length, measurements.len(), filename)));
+        }
+
+        let mut buffer = vec![0; length];
+        buffer[..4].copy_from_slice(&header);
+        if let Err(e) = reader.read_exact(&mut buffer[4..]) {
+            return Err(TipupError::from(format!("truncated document {} in measurement dump '{}': {}", measurements.len(), filename, e)));
+        }
+
+        position += length as u64;
+        match bson::decode_document(&mut Cursor::new(buffer)) {
+            Ok(document) => measurements.push(document),
+            Err(e) => return Err(TipupError::from(format!("failed to decode document {} in measurement dump '{}': {}", measurements.len(), filename, e))),
+        }
+    }
+
+    measurements.sort_by(compare_measurements);
+    Ok(measurements)
+}
+
+//reads a json array of documents, ex. analyzers in the format of the 'analyzers' collection or
+//blockpage fingerprints, description names the file in errors
+pub fn read_json_documents(filename: &str, description: &str) -> Result<Vec<OrderedDocument>, TipupError> {
+    let mut contents = String::new();
+    let result = File::open(filename).and_then(|mut file| file.read_to_string(&mut contents));
+    if let Err(e) = result {
+        return Err(TipupError::from(format!("failed to read {} '{}': {}", description, filename, e)));
+    }
+
+    let value: serde_json::Value = match serde_json::from_str(&contents) {
+        Ok(value) => value,
+        Err(e) => return Err(TipupError::from(format!("failed to parse {} '{}': {}", description, filename, e))),
+    };
+
+    match Bson::from_json(&value) {
+        Bson::Array(array) => {
+            let mut documents = Vec::new();
+            for x in array {
+                match x {
+                    Bson::Document(document) => documents.push(document),
+                    _ => return Err(TipupError::from(format!("{} '{}' contains a non document element", description, filename))),
+                }
+            }
+
+            Ok(documents)
+        },
+        _ => Err(TipupError::from(format!("{} '{}' is not a JSON array", description, filename))),
+    }
+}
+
+fn compare_measurements(a: &OrderedDocument, b: &OrderedDocument) -> Ordering {
+    let timestamp = |x: &OrderedDocument| match x.get("timestamp") {
+        Some(&Bson::I64(timestamp)) => timestamp,
+        _ => 0,
+    };
+
+    let id = |x: &OrderedDocument| match x.get("_id") {
+        Some(&Bson::ObjectId(ref id)) => id.bytes().to_vec(),
+        _ => Vec::new(),
+    };
+
+    timestamp(a).cmp(&timestamp(b)).then_with(|| id(a).cmp(&id(b)))
+}
@@ -1,5 +1,6 @@
 extern crate clap;
 extern crate mongodb;
+extern crate rhai;
 
 use flag_manager::Flag;
 
@@ -10,6 +11,7 @@ use std::fmt::{Display, Formatter, Result};
 pub enum TipupError {
     Clap(clap::Error),
     MongoDB(mongodb::Error),
+    Script(Box<rhai::EvalAltResult>),
     Send(std::sync::mpsc::SendError<Flag>),
     Tipup(String),
 }
@@ -19,6 +21,7 @@ impl Display for TipupError {
         match *self {
             TipupError::Clap(ref err) => write!(f, "ClapError: {}", err),
             TipupError::MongoDB(ref err) => write!(f, "MongoDBError: {}", err),
+            TipupError::Script(ref err) => write!(f, "ScriptError: {}", err),
             TipupError::Send(ref err) => write!(f, "Send: {}", err),
             TipupError::Tipup(ref err) => write!(f, "TipupError: {}", err),
         }
@@ -37,6 +40,12 @@ impl From<mongodb::Error> for TipupError {
     }
 }
 
+impl From<Box<rhai::EvalAltResult>> for TipupError {
+    fn from(err: Box<rhai::EvalAltResult>) -> TipupError {
+        TipupError::Script(err)
+    }
+}
+
 impl From<std::sync::mpsc::SendError<Flag>> for TipupError {
     fn from(err: std::sync::mpsc::SendError<Flag>) -> TipupError {
         TipupError::Send(err)
@@ -12,6 +12,8 @@ pub struct Flag {
     pub measurement_id: ObjectId,
     pub status: String,
     pub analyzer: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Document>,
 }
 
 impl Flag {
@@ -21,23 +23,39 @@ impl Flag {
             _ => return Err(TipupError::from("failed to parse measurement '_id' as ObjectId")),
         };
 
-        Ok(
-            Flag {
-                id: ObjectId::new().unwrap(),
-                measurement_id: measurement_id,
-                status: status.to_owned(),
-                analyzer: analyzer.to_owned(),
-            }
-        )
+        Ok(Flag::from_measurement_id(measurement_id, status, analyzer))
+    }
+
+    pub fn from_measurement_id(measurement_id: ObjectId, status: &str, analyzer: &str) -> Flag {
+        Flag {
+            id: ObjectId::new().unwrap(),
+            measurement_id: measurement_id,
+            status: status.to_owned(),
+            analyzer: analyzer.to_owned(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(document: &OrderedDocument, status: &str, analyzer: &str, details: Document) -> Result<Flag, TipupError> {
+        let mut flag = try!(Flag::new(document, status, analyzer));
+        flag.details = Some(details);
+        Ok(flag)
     }
 }
 
 pub struct FlagManager {
+    collection: String,
 }
 
 impl FlagManager {
     pub fn new() -> FlagManager {
+        FlagManager::with_collection("flags")
+    }
+
+    //writes flags to another collection, ex. to keep replayed flags apart from live ones
+    pub fn with_collection(collection: &str) -> FlagManager {
         FlagManager {
+            collection: collection.to_owned(),
         }
     }
 
@@ -48,7 +66,7 @@ impl FlagManager {
             _ => return Err(TipupError::from("failed to parse flag json as Bson::Document")),
         };
 
-        try!(tipup_db.collection("flags").insert_one(document, None));
+        try!(tipup_db.collection(&self.collection).insert_one(document, None));
 
         Ok(())
     }
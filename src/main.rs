@@ -2,14 +2,19 @@
 extern crate bson;
 #[macro_use]
 extern crate chan;
+extern crate chan_signal;
 #[macro_use]
 extern crate clap;
+extern crate crypto;
 extern crate dbscan;
 extern crate mongodb;
+extern crate regex;
+extern crate rhai;
 extern crate rustc_serialize;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 #[macro_use]
 extern crate slog;
 #[macro_use]
@@ -18,30 +23,57 @@ extern crate slog_term;
 extern crate time;
 
 use bson::Bson;
-use chan::Sender;
+use bson::ordered::OrderedDocument;
+use chan::{Receiver, Sender};
+use chan_signal::Signal;
 use clap::{App, ArgMatches};
 use mongodb::{Client, ClientInner, ClientOptions, ThreadedClient};
-use mongodb::coll::options::{CursorType, FindOneAndUpdateOptions, FindOptions};
+use mongodb::coll::options::{CursorType, FindOptions};
 use mongodb::db::{Database, ThreadedDatabase};
 use slog::{DrainExt, Logger};
 
 mod analyzer;
+mod checkpoint;
+mod config;
+mod dump;
 mod error;
 mod event_manager;
 mod flag_manager;
 mod pipe;
 mod result_window;
+mod rule;
+mod stream;
 
-use analyzer::{Analyzer, ErrorAnalyzer, StdDevAnalyzer};
+use analyzer::{Analyzer, BlockpageAnalyzer, CertificateAnalyzer, ChangePointAnalyzer, CompositeAnalyzer, CompositeChild, ConsensusAnalyzer, ContentDriftAnalyzer, DnsAnswerAnalyzer, ErrorAnalyzer, FailureRateAnalyzer, HeartbeatAnalyzer, MadAnalyzer, RuleAnalyzer, ScriptAnalyzer, SeasonalAnalyzer, StdDevAnalyzer};
+use checkpoint::Checkpoint;
+use config::{AnalyzerConfig, AnalyzerParameters, AnalyzerState};
 use error::TipupError;
 use event_manager::EventManager;
 use flag_manager::{Flag, FlagManager};
-use pipe::Pipe;
+use pipe::{CircuitBreaker, Pipe};
 use result_window::ResultWindow;
+use stream::{MeasurementStream, StreamEvent};
 
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
 
-fn parse_args(matches: &ArgMatches) -> Result<(String, u16, String, String, String, String, String, u32, u32), TipupError> {
+fn parse_breaker(matches: &ArgMatches) -> Result<CircuitBreaker, TipupError> {
+    let error_threshold = try!(value_t!(matches.value_of("ERROR_THRESHOLD"), usize));
+    let disable_interval = try!(value_t!(matches.value_of("DISABLE_INTERVAL"), i64));
+    if error_threshold == 0 {
+        return Err(TipupError::from("'error_threshold' must be at least 1"));
+    }
+
+    Ok(
+        CircuitBreaker {
+            error_threshold: error_threshold,
+            disable_seconds: disable_interval,
+        }
+    )
+}
+
+fn parse_args(matches: &ArgMatches) -> Result<(String, u16, String, String, String, String, String, u32, u32, u32, u32, u32, String, i64), TipupError> {
     let mongodb_ip_address = try!(value_t!(matches, "MONGODB_IP_ADDRESS", String));
     let mongodb_port = try!(value_t!(matches.value_of("MONGODB_PORT"), u16));
     let ca_file = try!(value_t!(matches.value_of("CA_FILE"), String));
@@ -51,18 +83,48 @@ fn parse_args(matches: &ArgMatches) -> Result<(String, u16, String, String, Stri
     let password = try!(value_t!(matches.value_of("PASSWORD"), String));
     let update_flags_interval = try!(value_t!(matches.value_of("UPDATE_FLAGS_INTERVAL"), u32));
     let update_events_interval = try!(value_t!(matches.value_of("UPDATE_EVENTS_INTERVAL"), u32));
+    let analyzer_tick_interval = try!(value_t!(matches.value_of("ANALYZER_TICK_INTERVAL"), u32));
+    let snapshot_interval = try!(value_t!(matches.value_of("SNAPSHOT_INTERVAL"), u32));
+    let reload_interval = try!(value_t!(matches.value_of("RELOAD_INTERVAL"), u32));
+    let ingestion_mode = try!(value_t!(matches.value_of("INGESTION_MODE"), String));
+    let lateness_allowance = try!(value_t!(matches.value_of("LATENESS_ALLOWANCE"), i64));
+    if lateness_allowance < 0 {
+        return Err(TipupError::from("'lateness_allowance' must not be negative"));
+    }
 
-    Ok((mongodb_ip_address, mongodb_port, ca_file, certificate_file, key_file, username, password, update_flags_interval, update_events_interval))
+    Ok((mongodb_ip_address, mongodb_port, ca_file, certificate_file, key_file, username, password, update_flags_interval, update_events_interval, analyzer_tick_interval, snapshot_interval, reload_interval, ingestion_mode, lateness_allowance))
 }
 
 fn main() {
     slog_scope::set_global_logger(Logger::root(slog_term::streamer().build().fuse(), o![]));
 
+    //register for shutdown signals before any threads are spawned
+    let signal = chan_signal::notify(&[Signal::INT, Signal::TERM]);
+
     //parse arguments
     let yaml = load_yaml!("args.yaml");
     let matches = App::from_yaml(yaml).get_matches();
 
-    let (mongodb_ip_address, mongodb_port, ca_file, certificate_file, key_file, username, password, update_flags_interval, update_events_interval) = match parse_args(&matches) {
+    let breaker = match parse_breaker(&matches) {
+        Ok(breaker) => breaker,
+        Err(e) => panic!("{}", e),
+    };
+
+    //replay a measurement dump offline, before the mongodb arguments are required
+    if let Some(replay_matches) = matches.subcommand_matches("replay-dump") {
+        let analyzer_tick_interval = match value_t!(matches.value_of("ANALYZER_TICK_INTERVAL"), u32) {
+            Ok(analyzer_tick_interval) => analyzer_tick_interval,
+            Err(e) => panic!("{}", e),
+        };
+
+        if let Err(e) = replay_dump(replay_matches, breaker, analyzer_tick_interval as i64) {
+            panic!("{}", e);
+        }
+
+        return;
+    }
+
+    let (mongodb_ip_address, mongodb_port, ca_file, certificate_file, key_file, username, password, update_flags_interval, update_events_interval, analyzer_tick_interval, snapshot_interval, reload_interval, ingestion_mode, lateness_allowance) = match parse_args(&matches) {
         Ok(args) => args,
         Err(e) => panic!("{}", e),
     };
@@ -72,32 +134,57 @@ fn main() {
         Ok(client) => client,
         Err(e) => panic!("{}", e),
     };
+
+    //check analyzer documents without starting the engine
+    if matches.subcommand_matches("validate-analyzers").is_some() {
+        let db = match initialize_db(&client, "proddle", &username, &password) {
+            Ok(db) => db,
+            Err(e) => panic!("{}", e),
+        };
+
+        match validate_analyzers(&db) {
+            Ok(0) => return,
+            Ok(_) => std::process::exit(1),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    //replay historical measurements without touching live state
+    if let Some(replay_matches) = matches.subcommand_matches("replay") {
+        let db = match initialize_db(&client, "proddle", &username, &password) {
+            Ok(db) => db,
+            Err(e) => panic!("{}", e),
+        };
+
+        if let Err(e) = replay(&db, replay_matches, breaker, analyzer_tick_interval as i64) {
+            panic!("{}", e);
+        }
+
+        return;
+    }
     
     //create pipe and result_window
     let result_window = Arc::new(RwLock::new(ResultWindow::new()));
     let (flag_tx, flag_rx) = chan::sync(50);
-    let mut pipe = Pipe::new();
+
+    let mut pipe = Pipe::new(result_window.clone(), breaker);
+    let mut loaded_analyzers = HashMap::new();
     {
         let db = match initialize_db(&client, "proddle", &username, &password) {
             Ok(db) => db,
             Err(e) => panic!("{}", e),
         };
 
-        if let Err(e) = load_analyzers(&db, &mut pipe, flag_tx, result_window.clone()) {
+        info!("loading analyzers");
+        if let Err(e) = reload_analyzers(&db, &mut pipe, &flag_tx, &result_window, &mut loaded_analyzers) {
             panic!("{}", e);
         }
-
-        info!("initializing result window");
-        let mut result_window = result_window.write().unwrap();
-        if let Err(e) = result_window.initialize(&db) {
-           panic!("{}", e);
-        }
     }
 
     //create flag manager and start
     info!("initializing flag manager");
     let (thread_username, thread_password) = (username.clone(), password.clone());
-    std::thread::spawn(move || {
+    let flag_manager_handle = std::thread::spawn(move || {
         let mut flag_buffer = Vec::new();
         let mut flag_manager = FlagManager::new();
         let process_flag_tick = chan::tick_ms(5 * 1000);
@@ -110,34 +197,45 @@ fn main() {
         loop {
             chan_select! {
                 flag_rx.recv() -> flag => {
-                    if let Some(flag) = flag {
-                        flag_buffer.push(flag);
+                    match flag {
+                        Some(flag) => flag_buffer.push(flag),
+                        None => {
+                            //every analyzer has been dropped, write remaining flags and exit
+                            if let Err(e) = write_flags(&client, &thread_username, &thread_password, &mut flag_manager, &mut flag_buffer) {
+                                error!("{}", e);
+                            }
+
+                            break;
+                        },
                     }
                 },
                 process_flag_tick.recv() => {
-                    if flag_buffer.len() > 0 {
-                        let db = match initialize_db(&client, "proddle", &thread_username, &thread_password) {
-                            Ok(db) => db,
-                            Err(e) => {
-                                error!("{}", e);
-                                continue;
-                            },
-                        };
-
-                        for flag in flag_buffer.iter() {
-                            if let Err(e) = flag_manager.process_flag(flag, &db) {
-                                error!("{}", e);
-                            }
-                        }
-                        
-                        info!("wrote {} new flag(s)", flag_buffer.len());
-                        flag_buffer.clear();
+                    if let Err(e) = write_flags(&client, &thread_username, &thread_password, &mut flag_manager, &mut flag_buffer) {
+                        error!("{}", e);
                     }
                 },
             }
         }
     });
 
+    //stream measurements when requested, polling remains the fallback
+    let (stream_tx, stream_rx) = chan::sync(1000);
+    let mut streaming = false;
+    let mut ingestion_checkpoint = IngestionCheckpoint {
+        resume_token: None,
+        checkpoints: HashMap::new(),
+    };
+
+    if ingestion_mode == "stream" {
+        match start_stream(&client, &username, &password, &pipe, lateness_allowance, stream_tx.clone(), &mut ingestion_checkpoint) {
+            Ok(_) => {
+                info!("streaming measurements");
+                streaming = true;
+            },
+            Err(e) => error!("failed to start measurement stream, polling instead: {}", e),
+        }
+    }
+
     //create event manager
     info!("initializing event manager");
     let event_manager = EventManager::new(604800); //7 days = 604800 seconds
@@ -146,9 +244,70 @@ fn main() {
     info!("TIPUP STARTED");
     let update_flags_tick = chan::tick_ms(update_flags_interval * 1000);
     let update_events_tick = chan::tick_ms(update_events_interval * 1000);
+    let analyzer_tick = chan::tick_ms(analyzer_tick_interval * 1000);
+    let snapshot_tick = chan::tick_ms(snapshot_interval * 1000);
+    let reload_tick = chan::tick_ms(reload_interval * 1000);
     loop {
         chan_select! {
+            signal.recv() -> signal => {
+                info!("received signal {:?}, shutting down", signal);
+                if let Err(e) = pipe.flush() {
+                    error!("{}", e);
+                }
+
+                match initialize_db(&client, "proddle", &username, &password) {
+                    Ok(db) => {
+                        if let Err(e) = save_ingestion_checkpoint(&db, &pipe, &mut ingestion_checkpoint) {
+                            error!("{}", e);
+                        }
+
+                        if let Err(e) = pipe.write_health(&db) {
+                            error!("{}", e);
+                        }
+                    },
+                    Err(e) => error!("{}", e),
+                }
+
+                break;
+            },
             update_flags_tick.recv() => {
+                if streaming {
+                    continue;
+                }
+
+                let db = match initialize_db(&client, "proddle", &username, &password) {
+                    Ok(db) => db,
+                    Err(e) => {
+                        error!("{}", e);
+                        continue;
+                    },
+                };
+
+                if let Err(e) = fetch_results(&db, &pipe, lateness_allowance, &mut ingestion_checkpoint.checkpoints) {
+                    error!("{}", e);
+                }
+            },
+            analyzer_tick.recv() => {
+                //polled measurements can arrive up to update_flags_interval late, so analyzers are
+                //ticked at the time up to which measurements are known to be ingested
+                let ingestion_latency = if streaming { 0 } else { update_flags_interval as i64 };
+                if let Err(e) = pipe.tick(time::now_utc().to_timespec().sec - ingestion_latency) {
+                    error!("{}", e);
+                }
+
+                for statistics in pipe.statistics() {
+                    info!("analyzer '{}' queue_depth:{} processed:{} mean_latency_ms:{:.3} max_latency_ms:{:.3}",
+                        statistics.name, statistics.queue_depth, statistics.processed, statistics.mean_latency_ms, statistics.max_latency_ms);
+                }
+
+                match initialize_db(&client, "proddle", &username, &password) {
+                    Ok(db) => if let Err(e) = pipe.write_health(&db) {
+                        error!("{}", e);
+                    },
+                    Err(e) => error!("{}", e),
+                }
+            },
+            snapshot_tick.recv() => {
                 let db = match initialize_db(&client, "proddle", &username, &password) {
                     Ok(db) => db,
                     Err(e) => {
@@ -157,7 +316,34 @@ fn main() {
                     },
                 };
 
-                if let Err(e) = fetch_results(&db, &pipe, result_window.clone()) {
+                if let Err(e) = save_ingestion_checkpoint(&db, &pipe, &mut ingestion_checkpoint) {
+                    error!("{}", e);
+                }
+            },
+            stream_rx.recv() -> event => {
+                match event {
+                    Some(StreamEvent::Measurement(document, resume_token)) => {
+                        stream_measurement(&pipe, &document, lateness_allowance, &mut ingestion_checkpoint);
+                        ingestion_checkpoint.resume_token = Some(resume_token);
+                    },
+                    Some(StreamEvent::Failed(e)) => {
+                        //polling resumes from the streamed checkpoints
+                        error!("measurement stream failed, falling back to polling: {}", e);
+                        streaming = false;
+                    },
+                    None => (),
+                }
+            },
+            reload_tick.recv() => {
+                let db = match initialize_db(&client, "proddle", &username, &password) {
+                    Ok(db) => db,
+                    Err(e) => {
+                        error!("{}", e);
+                        continue;
+                    },
+                };
+
+                if let Err(e) = reload_analyzers(&db, &mut pipe, &flag_tx, &result_window, &mut loaded_analyzers) {
                     error!("{}", e);
                 }
             },
@@ -176,6 +362,15 @@ fn main() {
             },
         }
     }
+
+    //dropping the pipe closes the flag channel so the flag manager writes any remaining flags
+    drop(pipe);
+    drop(flag_tx);
+    if let Err(e) = flag_manager_handle.join() {
+        error!("flag manager panicked: {:?}", e);
+    }
+
+    info!("TIPUP STOPPED");
 }
 
 fn initialize_mongodb_client(mongodb_ip_address: &str, mongodb_port: u16, ca_file: &str, certificate_file: &str, key_file: &str) -> Result<Arc<ClientInner>, mongodb::Error> {
@@ -187,71 +382,589 @@ fn initialize_mongodb_client(mongodb_ip_address: &str, mongodb_port: u16, ca_fil
     }
 }
 
+fn write_flags(client: &Client, username: &str, password: &str, flag_manager: &mut FlagManager, flag_buffer: &mut Vec<Flag>) -> Result<(), TipupError> {
+    if flag_buffer.len() == 0 {
+        return Ok(());
+    }
+
+    let db = try!(initialize_db(client, "proddle", username, password));
+    for flag in flag_buffer.iter() {
+        if let Err(e) = flag_manager.process_flag(flag, &db) {
+            error!("{}", e);
+        }
+    }
+
+    info!("wrote {} new flag(s)", flag_buffer.len());
+    flag_buffer.clear();
+    Ok(())
+}
+
 fn initialize_db(client: &Client, db_name: &str, username: &str, password: &str) -> Result<Database, TipupError> {
     let db = client.db(db_name);
     try!(db.auth(&username, &password));
     Ok(db)
 }
 
-fn load_analyzers(db: &Database, pipe: &mut Pipe, flag_tx: Sender<Flag>, result_window: Arc<RwLock<ResultWindow>>) -> Result<(), TipupError> {
-    //query mongodb for analyzer definitions
-    let mut count = 0;
+//the config an analyzer in the pipe was built from
+struct LoadedAnalyzer {
+    fingerprint: String,
+    state: AnalyzerState,
+}
+
+//brings the pipe in line with the analyzers collection, adding, removing, pausing and
+//reconfiguring analyzers whose documents changed since the previous call
+fn reload_analyzers(db: &Database, pipe: &mut Pipe, flag_tx: &Sender<Flag>, result_window: &Arc<RwLock<ResultWindow>>,
+        loaded_analyzers: &mut HashMap<String, LoadedAnalyzer>) -> Result<(), TipupError> {
+    //query mongodb for analyzer configs, all are read first so composites can reference children
+    let mut configs = Vec::new();
+    let mut invalid = HashSet::new();
     let cursor = try!(db.collection("analyzers").find(None, None));
     for document in cursor {
-        //parse document
         let document = try!(document);
-        info!("loading analyzer: {:?}", document);
+        match AnalyzerConfig::from_document(&document) {
+            Ok(config) => configs.push(config),
+            Err(e) => {
+                //an invalid document leaves the analyzer as it is
+                error!("skipping invalid {}", e);
+                if let Some(name) = e.name {
+                    invalid.insert(name);
+                }
+            },
+        }
+    }
 
-        let name = match document.get("name") {
-            Some(&Bson::String(ref name)) => name,
-            _ => return Err(TipupError::from("failed to parse analyzer name")),
-        };
+    //children of composite analyzers report to their parent rather than the flag manager
+    let mut children = HashSet::new();
+    for config in configs.iter() {
+        children.extend(config.children());
+    }
 
-        let class = match document.get("class") {
-            Some(&Bson::String(ref class)) => class,
-            _ => return Err(TipupError::from("failed to parse analyzer class")),
-        };
+    let mut current = HashSet::new();
+    for config in configs.iter() {
+        let name = &config.name;
+        if children.contains(name) {
+            continue;
+        }
 
-        let status = match document.get("status") {
-            Some(&Bson::String(ref status)) => status,
-            _ => return Err(TipupError::from("failed to parse analyzer status")),
+        current.insert(name.to_owned());
+        let fingerprint = config_fingerprint(config, &configs, &mut Vec::new());
+        let previous_state = loaded_analyzers.get(name).map(|x| (x.state, x.fingerprint != fingerprint));
+        let result = match (previous_state, config.state) {
+            (None, AnalyzerState::Retired) => continue,
+            (None, _) => {
+                info!("adding analyzer '{}'", name);
+                add_analyzer(config, &configs, db, pipe, flag_tx, result_window)
+            },
+            (Some(_), AnalyzerState::Retired) => {
+                info!("retiring analyzer '{}'", name);
+                loaded_analyzers.remove(name);
+                remove_analyzer(config, &configs, pipe, result_window)
+            },
+            (Some((state, changed)), _) => {
+                let mut result = Ok(());
+                if changed {
+                    info!("reconfiguring analyzer '{}'", name);
+                    result = reconfigure_analyzer(config, &configs, db, pipe, flag_tx, result_window);
+                }
+
+                if result.is_ok() && state != config.state {
+                    info!("{} analyzer '{}'", if config.state == AnalyzerState::Paused { "pausing" } else { "resuming" }, name);
+                    result = pipe.set_paused(name, config.state == AnalyzerState::Paused);
+                }
+
+                result
+            },
         };
 
-        let measurement_class = match document.get("measurement_class") {
-            Some(&Bson::String(ref measurement_class)) => measurement_class,
-            _ => return Err(TipupError::from("failed to parse analyzer measurement_class")),
+        match result {
+            Ok(_) if config.state != AnalyzerState::Retired => {
+                loaded_analyzers.insert(name.to_owned(), LoadedAnalyzer {
+                    fingerprint: fingerprint,
+                    state: config.state,
+                });
+            },
+            Ok(_) => (),
+            Err(e) => error!("failed to update analyzer '{}' (_id {}): {}", name, config::format_id(&config.id), e),
+        }
+    }
+
+    //remove analyzers whose documents were deleted or that became composite children
+    let removed: Vec<String> = loaded_analyzers.keys()
+        .filter(|x| !current.contains(*x) && !invalid.contains(*x))
+        .cloned()
+        .collect();
+    for name in removed {
+        info!("removing analyzer '{}'", name);
+        loaded_analyzers.remove(&name);
+        if let Err(e) = pipe.remove_analyzer(&name) {
+            error!("{}", e);
+        }
+
+        let mut result_window = result_window.write().unwrap();
+        result_window.retire(&name);
+        result_window.release(&name);
+    }
+
+    Ok(())
+}
+
+//prints every problem in the analyzers collection and returns the number of invalid documents
+fn validate_analyzers(db: &Database) -> Result<usize, TipupError> {
+    let (mut configs, mut total, mut invalid) = (Vec::new(), 0, 0);
+    let cursor = try!(db.collection("analyzers").find(None, None));
+    for document in cursor {
+        let document = try!(document);
+        total += 1;
+        match AnalyzerConfig::from_document(&document) {
+            Ok(config) => configs.push(config),
+            Err(e) => {
+                println!("{}", e);
+                invalid += 1;
+            },
+        }
+    }
+
+    //construct decoded analyzers against scratch state to run the checks of each class
+    let result_window = Arc::new(RwLock::new(ResultWindow::new()));
+    let (flag_tx, _flag_rx) = chan::async();
+    for config in configs.iter() {
+        if let Err(e) = create_analyzer(config, &configs, Some(db), flag_tx.clone(), result_window.clone(), &mut Vec::new()) {
+            println!("analyzer '{}' (_id {}): {}", config.name, config::format_id(&config.id), e);
+            invalid += 1;
+        }
+    }
+
+    println!("{} of {} analyzer document(s) valid", total - invalid, total);
+    Ok(invalid)
+}
+
+//runs the analyzers over measurements in the --from and --to range, flags are written to the
+//--flags_collection or printed, while checkpoints, snapshots and health are left untouched
+fn replay(db: &Database, matches: &ArgMatches, breaker: CircuitBreaker, tick_interval: i64) -> Result<(), TipupError> {
+    let from = try!(value_t!(matches.value_of("FROM"), i64));
+    let to = try!(value_t!(matches.value_of("TO"), i64));
+    if from > to {
+        return Err(TipupError::from("'from' must not be after 'to'"));
+    }
+
+    let flags_collection = matches.value_of("FLAGS_COLLECTION").map(|x| x.to_owned());
+    if flags_collection.as_ref().map(|x| x.as_str()) == Some("flags") {
+        return Err(TipupError::from("replayed flags can not be written to the live 'flags' collection"));
+    }
+
+    //read analyzer configs, invalid documents are skipped as they are by the live engine
+    let mut configs = Vec::new();
+    let cursor = try!(db.collection("analyzers").find(None, None));
+    for document in cursor {
+        match AnalyzerConfig::from_document(&try!(document)) {
+            Ok(config) => configs.push(config),
+            Err(e) => error!("skipping invalid {}", e),
+        }
+    }
+
+    let result_window = Arc::new(RwLock::new(ResultWindow::new()));
+    let (flag_tx, flag_rx) = chan::async();
+    let mut pipe = Pipe::new(result_window.clone(), breaker);
+    try!(add_replay_analyzers(&configs, Some(db), &mut pipe, &flag_tx, &result_window));
+
+    let flag_output = flags_collection.map(|x| (FlagManager::with_collection(&x), db.clone()));
+    let flag_handle = write_replayed_flags(flag_rx, flag_output);
+
+    //replay measurements of every host in (timestamp, _id) order
+    let timestamp_range = doc!("$gte" => from, "$lte" => to);
+    let search_document = Some(doc!("timestamp" => timestamp_range));
+    let sort_document = Some(doc!("timestamp" => 1, "_id" => 1));
+    let find_options = Some(FindOptions {
+        allow_partial_results: false,
+        no_cursor_timeout: true,
+        oplog_replay: false,
+        skip: None,
+        limit: None,
+        cursor_type: CursorType::NonTailable,
+        batch_size: None,
+        comment: None,
+        max_time_ms: None,
+        modifiers: None,
+        projection: None,
+        sort: sort_document,
+        read_preference: None,
+    });
+
+    info!("replaying measurements from {} to {}", from, to);
+    let cursor = try!(db.collection("measurements").find(search_document, find_options));
+    let count = try!(replay_measurements(&pipe, cursor.map(|x| x.map_err(TipupError::from)), tick_interval));
+
+    //dropping the pipe closes the flag channel once every analyzer is done
+    drop(pipe);
+    drop(flag_tx);
+    let flag_count = match flag_handle.join() {
+        Ok(flag_count) => flag_count,
+        Err(_) => return Err(TipupError::from("flag writer panicked")),
+    };
+
+    info!("replayed {} measurement(s), raised {} flag(s)", count, flag_count);
+    Ok(())
+}
+
+//runs the analyzers in an analyzer file over a chimpanzee measurement dump without mongodb,
+//printing flags as JSON
+fn replay_dump(matches: &ArgMatches, breaker: CircuitBreaker, tick_interval: i64) -> Result<(), TipupError> {
+    let dump_file = try!(value_t!(matches.value_of("DUMP_FILE"), String));
+    let analyzers_file = try!(value_t!(matches.value_of("ANALYZERS_FILE"), String));
+
+    //every document must be valid, unlike the analyzers collection there is nothing to fall back to
+    let mut configs = Vec::new();
+    for document in try!(dump::read_json_documents(&analyzers_file, "analyzer file")) {
+        match AnalyzerConfig::from_document(&document) {
+            Ok(config) => configs.push(config),
+            Err(e) => return Err(TipupError::from(format!("invalid {}", e))),
+        }
+    }
+
+    let measurements = try!(dump::read_measurements(&dump_file));
+    info!("read {} measurement(s) from '{}'", measurements.len(), dump_file);
+    info!("only flags are replayed, event grouping is disabled in the engine");
+
+    let result_window = Arc::new(RwLock::new(ResultWindow::new()));
+    let (flag_tx, flag_rx) = chan::async();
+    let mut pipe = Pipe::new(result_window.clone(), breaker);
+    try!(add_replay_analyzers(&configs, None, &mut pipe, &flag_tx, &result_window));
+    let flag_handle = write_replayed_flags(flag_rx, None);
+
+    let count = try!(replay_measurements(&pipe, measurements.into_iter().map(Ok), tick_interval));
+
+    drop(pipe);
+    drop(flag_tx);
+    let flag_count = match flag_handle.join() {
+        Ok(flag_count) => flag_count,
+        Err(_) => return Err(TipupError::from("flag writer panicked")),
+    };
+
+    info!("replayed {} measurement(s), raised {} flag(s)", count, flag_count);
+    Ok(())
+}
+
+//writes replayed flags to a collection when given, otherwise prints them as JSON lines, and
+//returns the number of flags once the channel closes
+fn write_replayed_flags(flag_rx: Receiver<Flag>, flag_output: Option<(FlagManager, Database)>) -> JoinHandle<usize> {
+    std::thread::spawn(move || {
+        let mut flag_output = flag_output;
+        let mut count = 0;
+        for flag in flag_rx {
+            match flag_output {
+                Some((ref mut flag_manager, ref db)) => if let Err(e) = flag_manager.process_flag(&flag, db) {
+                    error!("{}", e);
+                },
+                None => match serde_json::to_string(&flag) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => error!("failed to serialize flag: {}", e),
+                },
+            }
+
+            count += 1;
+        }
+
+        count
+    })
+}
+
+//adds every analyzer that is not retired, paused included, with fresh state
+fn add_replay_analyzers(configs: &Vec<AnalyzerConfig>, db: Option<&Database>, pipe: &mut Pipe, flag_tx: &Sender<Flag>,
+        result_window: &Arc<RwLock<ResultWindow>>) -> Result<(), TipupError> {
+    let mut children = HashSet::new();
+    for config in configs.iter() {
+        children.extend(config.children());
+    }
+
+    for config in configs.iter() {
+        if children.contains(&config.name) || config.state == AnalyzerState::Retired {
+            continue;
+        }
+
+        let analyzer = match create_analyzer(config, configs, db, flag_tx.clone(), result_window.clone(), &mut Vec::new()) {
+            Ok(analyzer) => analyzer,
+            Err(e) => return Err(TipupError::from(format!("failed to create analyzer '{}' (_id {}): {}", config.name, config::format_id(&config.id), e))),
         };
 
-        let fields: Vec<String> = match document.get("fields") {
-            Some(&Bson::Array(ref fields)) => fields.iter().map(|x| x.to_string().replace("\"", "")).collect(),
-            _ => return Err(TipupError::from("failed to parse analyzer fields")),
+        try!(pipe.add_analyzer(config.name.to_owned(), config.measurement_class.clone(), config.filter.clone(), analyzer));
+    }
+
+    Ok(())
+}
+
+//sends measurements through the pipe in order, ticking analyzers on measurement time rather
+//than the clock, and flushes pending flags at the end
+fn replay_measurements<I>(pipe: &Pipe, measurements: I, tick_interval: i64) -> Result<usize, TipupError>
+        where I: Iterator<Item=Result<OrderedDocument, TipupError>> {
+    let (mut count, mut next_tick) = (0, None);
+    for document in measurements {
+        let document = try!(document);
+        let timestamp = match document.get("timestamp") {
+            Some(&Bson::I64(timestamp)) => timestamp,
+            _ => return Err(TipupError::from(format!("failed to parse 'timestamp' value in measurement {:?}", document.get("_id")))),
         };
 
-        let parameters = match document.get("parameters") {
-            Some(&Bson::Array(ref parameters)) => parameters,
-            _ => return Err(TipupError::from("failed to parse analyzer parameters")),
+        match next_tick {
+            Some(tick) if timestamp >= tick => {
+                try!(pipe.tick(timestamp));
+                next_tick = Some(timestamp + tick_interval);
+            },
+            None => next_tick = Some(timestamp + tick_interval),
+            _ => (),
+        }
+
+        if let Err(e) = pipe.send_measurement(&document) {
+            error!("failed to send measurement {:?}: {}", document.get("_id"), e);
+        }
+
+        //bound the measurements queued ahead of the analyzers
+        count += 1;
+        if count % 1000 == 0 {
+            pipe.drain();
+        }
+    }
+
+    try!(pipe.flush());
+    Ok(count)
+}
+
+fn add_analyzer(config: &AnalyzerConfig, configs: &Vec<AnalyzerConfig>, db: &Database, pipe: &mut Pipe,
+        flag_tx: &Sender<Flag>, result_window: &Arc<RwLock<ResultWindow>>) -> Result<(), TipupError> {
+    let analyzer = match create_analyzer(config, configs, Some(db), flag_tx.clone(), result_window.clone(), &mut Vec::new()) {
+        Ok(analyzer) => analyzer,
+        Err(e) => {
+            //drop any windows registered before the failure
+            release_windows(config, configs, result_window);
+            return Err(e);
+        },
+    };
+
+    try!(pipe.add_analyzer(config.name.to_owned(), config.measurement_class.clone(), config.filter.clone(), analyzer));
+    if config.state == AnalyzerState::Paused {
+        try!(pipe.set_paused(&config.name, true));
+    }
+
+    //remove the analyzer so the next reload adds it again
+    if let Err(e) = pipe.initialize_analyzer(db, &config.name) {
+        try!(remove_analyzer(config, configs, pipe, result_window));
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+fn remove_analyzer(config: &AnalyzerConfig, configs: &Vec<AnalyzerConfig>, pipe: &mut Pipe,
+        result_window: &Arc<RwLock<ResultWindow>>) -> Result<(), TipupError> {
+    try!(pipe.remove_analyzer(&config.name));
+    release_windows(config, configs, result_window);
+    Ok(())
+}
+
+fn reconfigure_analyzer(config: &AnalyzerConfig, configs: &Vec<AnalyzerConfig>, db: &Database, pipe: &mut Pipe,
+        flag_tx: &Sender<Flag>, result_window: &Arc<RwLock<ResultWindow>>) -> Result<(), TipupError> {
+    let mut names = Vec::new();
+    analyzer_names(config, configs, &mut names);
+
+    pipe.reconfigure_analyzer(&config.name, config.measurement_class.clone(), config.filter.clone(), |analyzer| {
+        let snapshot = match analyzer.snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!("failed to snapshot analyzer '{}' before reconfiguring: {}", config.name, e);
+                None
+            },
         };
 
-        //create analyzer
-        let analyzer = match class.as_ref() {
-            "ErrorAnalyzer" => Box::new(try!(ErrorAnalyzer::new(name, status, fields, flag_tx.clone()))) as Box<Analyzer>,
-            //"StdDevAnalyzer" => Box::new(try!(StdDevAnalyzer::new(name, parameters, result_window.clone(), flag_tx.clone()))) as Box<Analyzer>,
-            _ => return Err(TipupError::from("unknown analyzer class")),
+        //compatible windows are reused by the replacement's registrations
+        {
+            let mut result_window = result_window.write().unwrap();
+            for name in names.iter() {
+                result_window.retire(name);
+            }
+        }
+
+        let mut replacement = match create_analyzer(config, configs, Some(db), flag_tx.clone(), result_window.clone(), &mut Vec::new()) {
+            Ok(replacement) => replacement,
+            Err(e) => {
+                let mut result_window = result_window.write().unwrap();
+                for name in names.iter() {
+                    result_window.reinstate(name);
+                }
+
+                return Err(e);
+            },
         };
 
-        //add analyzer to pipe
-        try!(pipe.add_analyzer(name.to_owned(), measurement_class.to_owned(), analyzer));
-        count += 1;
+        {
+            let mut result_window = result_window.write().unwrap();
+            for name in names.iter() {
+                result_window.release(name);
+            }
+        }
+
+        //carry over state when the replacement accepts the previous snapshot
+        if let Some(ref snapshot) = snapshot {
+            if let Err(e) = replacement.restore(snapshot) {
+                warn!("analyzer '{}' could not restore its previous state, reinitializing: {}", config.name, e);
+            }
+        }
+
+        try!(replacement.initialize(db));
+
+        Ok(replacement)
+    })
+}
+
+fn release_windows(config: &AnalyzerConfig, configs: &Vec<AnalyzerConfig>, result_window: &Arc<RwLock<ResultWindow>>) {
+    let mut names = Vec::new();
+    analyzer_names(config, configs, &mut names);
+
+    let mut result_window = result_window.write().unwrap();
+    for name in names.iter() {
+        result_window.retire(name);
+        result_window.release(name);
+    }
+}
+
+//names of the analyzer and, for composites, every descendant, which each own result windows
+fn analyzer_names(config: &AnalyzerConfig, configs: &Vec<AnalyzerConfig>, names: &mut Vec<String>) {
+    if names.contains(&config.name) {
+        return;
     }
 
-    if count > 0 {
-        info!("loaded {} analyzer(s)", count);
+    names.push(config.name.to_owned());
+    for child in config.children() {
+        if let Some(child_config) = configs.iter().find(|x| x.name == child) {
+            analyzer_names(child_config, configs, names);
+        }
+    }
+}
+
+//identifies the documents an analyzer is built from, so any change to them is detected
+fn config_fingerprint(config: &AnalyzerConfig, configs: &Vec<AnalyzerConfig>, names: &mut Vec<String>) -> String {
+    analyzer_names(config, configs, names);
+    names.iter()
+        .filter_map(|x| configs.iter().find(|y| &y.name == x))
+        .map(|x| format!("{:?}", x.source))
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+fn create_analyzer(config: &AnalyzerConfig, configs: &Vec<AnalyzerConfig>, db: Option<&Database>, flag_tx: Sender<Flag>,
+        result_window: Arc<RwLock<ResultWindow>>, parents: &mut Vec<String>) -> Result<Box<Analyzer>, TipupError> {
+    let (name, status) = (&config.name, &config.status);
+    let analyzer = match config.parameters {
+        AnalyzerParameters::Blockpage(ref parameters) => Box::new(try!(BlockpageAnalyzer::new(name, status, parameters, db, flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::Certificate(ref parameters) => Box::new(try!(CertificateAnalyzer::new(name, status, parameters, flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::ChangePoint(ref parameters) => Box::new(try!(ChangePointAnalyzer::new(name, status, parameters, result_window.clone(), flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::Composite(ref parameters) => {
+            if parents.contains(name) {
+                return Err(TipupError::from(format!("composite analyzer '{}' contains itself", name)));
+            }
+
+            //build children with a private channel read by the composite
+            let (child_tx, child_rx) = chan::async();
+            let mut children = Vec::new();
+            parents.push(name.to_owned());
+            for child_name in try!(CompositeAnalyzer::parse_children(parameters, name)) {
+                let child_config = match configs.iter().find(|x| x.name == child_name) {
+                    Some(child_config) => child_config,
+                    None => return Err(TipupError::from(format!("unknown child analyzer '{}' in composite analyzer '{}'", child_name, name))),
+                };
+
+                children.push(CompositeChild {
+                    name: child_name.to_owned(),
+                    measurement_class: child_config.measurement_class.clone(),
+                    filter: child_config.filter.clone(),
+                    analyzer: try!(create_analyzer(child_config, configs, db, child_tx.clone(), result_window.clone(), parents)),
+                });
+            }
+
+            parents.pop();
+            Box::new(try!(CompositeAnalyzer::new(name, status, parameters, children, child_rx, result_window.clone(), flag_tx.clone()))) as Box<Analyzer>
+        },
+        AnalyzerParameters::Consensus(ref parameters) => Box::new(try!(ConsensusAnalyzer::new(name, status, parameters, result_window.clone(), flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::ContentDrift(ref parameters) => Box::new(try!(ContentDriftAnalyzer::new(name, status, parameters, result_window.clone(), flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::DnsAnswer(ref parameters) => Box::new(try!(DnsAnswerAnalyzer::new(name, status, parameters, flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::Error => Box::new(try!(ErrorAnalyzer::new(name, status, config.fields.clone(), flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::FailureRate(ref parameters) => Box::new(try!(FailureRateAnalyzer::new(name, status, parameters, flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::Heartbeat(ref parameters) => Box::new(try!(HeartbeatAnalyzer::new(name, status, parameters, flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::Mad(ref parameters) => Box::new(try!(MadAnalyzer::new(name, status, parameters, result_window.clone(), flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::Rule(ref parameters) => Box::new(try!(RuleAnalyzer::new(name, status, parameters, flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::Script(ref parameters) => Box::new(try!(ScriptAnalyzer::new(name, status, parameters, flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::Seasonal(ref parameters) => Box::new(try!(SeasonalAnalyzer::new(name, status, parameters, result_window.clone(), flag_tx.clone()))) as Box<Analyzer>,
+        AnalyzerParameters::StdDev(ref parameters) => Box::new(try!(StdDevAnalyzer::new(name, status, parameters, result_window.clone(), flag_tx.clone()))) as Box<Analyzer>,
+    };
+
+    Ok(analyzer)
+}
+
+//progress of polled and streamed measurements, persisted with analyzer snapshots
+struct IngestionCheckpoint {
+    resume_token: Option<Bson>,
+    checkpoints: HashMap<String, Checkpoint>,
+}
+
+fn start_stream(client: &Client, username: &str, password: &str, pipe: &Pipe, lateness_allowance: i64,
+        stream_tx: Sender<StreamEvent>, ingestion_checkpoint: &mut IngestionCheckpoint) -> Result<(), TipupError> {
+    let db = try!(initialize_db(client, "proddle", username, password));
+    let stream = try!(MeasurementStream::open(&db, client.db("local")));
+
+    //poll measurements from before the stream position, streamed measurements the poll already
+    //analyzed are skipped using the resulting checkpoints
+    ingestion_checkpoint.checkpoints = try!(Checkpoint::load_all(&db, lateness_allowance));
+    try!(fetch_results(&db, pipe, lateness_allowance, &mut ingestion_checkpoint.checkpoints));
+
+    std::thread::spawn(move || stream.run(stream_tx));
+    Ok(())
+}
+
+fn stream_measurement(pipe: &Pipe, document: &OrderedDocument, lateness_allowance: i64, ingestion_checkpoint: &mut IngestionCheckpoint) {
+    let (hostname, timestamp, measurement_id) = match (document.get("vantage_hostname"), document.get("timestamp"), document.get("_id")) {
+        (Some(&Bson::String(ref hostname)), Some(&Bson::I64(timestamp)), Some(&Bson::ObjectId(ref measurement_id))) => (hostname, timestamp, measurement_id),
+        _ => {
+            error!("failed to parse 'vantage_hostname', 'timestamp' and '_id' of streamed measurement {:?}", document.get("_id"));
+            return;
+        },
+    };
+
+    let checkpoint = ingestion_checkpoint.checkpoints.entry(hostname.to_owned()).or_insert_with(|| Checkpoint::new(hostname, lateness_allowance));
+    if checkpoint.contains(timestamp, measurement_id) {
+        return;
+    }
+
+    if let Err(e) = pipe.send_measurement(document) {
+        error!("failed to send measurement {:?}: {}", document.get("_id"), e);
+    }
+
+    checkpoint.advance(timestamp, measurement_id);
+}
+
+//writes analyzer snapshots, then the checkpoints of the measurements they include, then the
+//resume token, so a restart neither skips measurements nor restores state that lacks them
+fn save_ingestion_checkpoint(db: &Database, pipe: &Pipe, ingestion_checkpoint: &mut IngestionCheckpoint) -> Result<(), TipupError> {
+    try!(pipe.snapshot(db));
+
+    //a host that fails to save does not stop the others, but the resume token must not pass it
+    let mut saved = true;
+    for (hostname, checkpoint) in ingestion_checkpoint.checkpoints.iter_mut() {
+        if let Err(e) = checkpoint.save(db) {
+            error!("failed to save checkpoint for host '{}': {}", hostname, e);
+            saved = false;
+        }
+    }
+
+    if !saved {
+        return Err(TipupError::from("failed to save every host checkpoint, resume token not saved"));
+    }
+
+    if let Some(ref resume_token) = ingestion_checkpoint.resume_token {
+        try!(stream::save_resume_token(db, resume_token));
     }
 
     Ok(())
 }
 
-fn fetch_results(db: &Database, pipe: &Pipe, result_window: Arc<RwLock<ResultWindow>>) -> Result<(), TipupError> {
+fn fetch_results(db: &Database, pipe: &Pipe, lateness_allowance: i64, checkpoints: &mut HashMap<String, Checkpoint>) -> Result<(), TipupError> {
     //iterate over distinct hostnames for measurements
     let mut count = 0;
     let hostname_cursor = try!(db.collection("measurements").distinct("vantage_hostname", None, None));
@@ -261,29 +974,16 @@ fn fetch_results(db: &Database, pipe: &Pipe, result_window: Arc<RwLock<ResultWin
             _ => continue,
         };
 
-        //query db for timestamp of last seen result
-        let search_document = Some(doc!("vantage_hostname" => hostname));
-        let document = try!(db.collection("analyzed_measurements").find_one(search_document, None));
-        let timestamp = match document {
-            Some(document) => {
-                match document.get("timestamp") {
-                    Some(&Bson::I64(timestamp)) => timestamp,
-                    _ => return Err(TipupError::from(format!("failed to parse 'timestamp' value in analyzed_measurements for host '{}'", hostname))),
-                }
-            },
-            None => 0,
-        };
+        //query db for position of last analyzed measurement the first time a host is seen
+        if !checkpoints.contains_key(hostname) {
+            let checkpoint = try!(Checkpoint::load(db, hostname, lateness_allowance));
+            checkpoints.insert(hostname.to_owned(), checkpoint);
+        }
 
-        //iterate over newest measurements
-        let gt = doc!("$gt" => timestamp);
-        let search_document = Some(doc!(
-            "vantage_hostname" => hostname,
-            "timestamp" => gt
-        ));
+        let checkpoint = checkpoints.get_mut(hostname).unwrap();
 
         //create find options
-        let negative_one = -1;
-        let sort_document = Some(doc!("timestamp" => negative_one));
+        let sort_document = Some(doc!("timestamp" => 1, "_id" => 1));
         let find_options = Some(FindOptions {
             allow_partial_results: false,
             no_cursor_timeout: false,
@@ -300,45 +1000,30 @@ fn fetch_results(db: &Database, pipe: &Pipe, result_window: Arc<RwLock<ResultWin
             read_preference: None,
         });
 
-        //iterate over new measurements
-        let cursor = try!(db.collection("measurements").find(search_document, find_options));
-        let mut max_timestamp = -1;
+        //iterate over new measurements oldest first
+        let cursor = try!(db.collection("measurements").find(Some(checkpoint.search_document()), find_options));
         for document in cursor {
             let document = try!(document);
-            if let Err(e) = pipe.send_measurement(&document) {
-                panic!("document:{:?} err:{}", document, e);
-            }
 
-            match document.get("timestamp") {
-                Some(&Bson::I64(result_timestamp)) => max_timestamp = std::cmp::max(max_timestamp, result_timestamp),
-                _ => return Err(TipupError::from("failed to parse 'timestamp' value in result")),
+            let (timestamp, measurement_id) = match (document.get("timestamp"), document.get("_id")) {
+                (Some(&Bson::I64(timestamp)), Some(&Bson::ObjectId(ref measurement_id))) => (timestamp, measurement_id.clone()),
+                _ => {
+                    error!("skipping measurement {:?} from host '{}', failed to parse 'timestamp' and '_id' values", document.get("_id"), hostname);
+                    continue;
+                },
+            };
+
+            if checkpoint.contains(timestamp, &measurement_id) {
+                continue;
             }
 
-            //add result to result window
-            {
-                let mut result_window = result_window.write().unwrap();
-                try!(result_window.add_result(document))
+            if let Err(e) = pipe.send_measurement(&document) {
+                error!("failed to send measurement {:?}: {}", document.get("_id"), e);
             }
 
+            checkpoint.advance(timestamp, &measurement_id);
             count += 1;
         }
-
-        //update db with most recenlty analyzed result timestamp
-        if max_timestamp != -1 {
-            let search_document = doc!("vantage_hostname" => hostname);
-            let update_timestamp_document = doc!("timestamp" => max_timestamp);
-            let update_document = doc!("$set" => update_timestamp_document);
-            let update_options = Some(FindOneAndUpdateOptions {
-                return_document: None,
-                max_time_ms: None,
-                projection: None,
-                sort: None,
-                upsert: Some(true),
-                write_concern: None,
-            });
-
-            try!(db.collection("analyzed_measurements").find_one_and_update(search_document, update_document, update_options));
-        }
     }
 
     if count > 0 {
@@ -1,31 +1,352 @@
 use bson::Bson;
 use bson::ordered::OrderedDocument;
+use chan::{self, Sender};
+use mongodb::coll::options::{FindOneAndUpdateOptions, UpdateOptions};
+use mongodb::db::{Database, ThreadedDatabase};
+use regex::{self, Regex};
+use time;
 
 use analyzer::Analyzer;
 use error::TipupError;
+use result_window::ResultWindow;
+use rule::Rule;
 
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
+//measurements queued per analyzer before send_measurement blocks, so a slow analyzer slows
+//ingestion rather than buffering the whole backlog in memory
+const QUEUE_LENGTH: usize = 1000;
+
+//class patterns are exact names, globs with '*' and '?', or regular expressions between slashes
+#[derive(Clone)]
+pub struct ClassMatcher {
+    patterns: Vec<Regex>,
+}
+
+impl ClassMatcher {
+    pub fn parse(value: &Bson) -> Result<ClassMatcher, TipupError> {
+        let patterns: Vec<&str> = match *value {
+            Bson::String(ref pattern) => vec!(pattern),
+            Bson::Array(ref array) if array.len() > 0 => {
+                let mut patterns = Vec::new();
+                for x in array {
+                    match *x {
+                        Bson::String(ref pattern) => patterns.push(pattern.as_str()),
+                        _ => return Err(TipupError::from("failed to parse measurement_class element as String")),
+                    }
+                }
+
+                patterns
+            },
+            _ => return Err(TipupError::from("failed to parse measurement_class as a String or non empty Array")),
+        };
+
+        let mut regexes = Vec::new();
+        for pattern in patterns {
+            let expression = if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+                pattern[1..pattern.len() - 1].to_owned()
+            } else {
+                //escape everything except glob wildcards
+                let mut expression = String::from("^");
+                for c in pattern.chars() {
+                    match c {
+                        '*' => expression.push_str(".*"),
+                        '?' => expression.push('.'),
+                        c => expression.push_str(&regex::escape(&c.to_string())),
+                    }
+                }
+
+                expression.push('$');
+                expression
+            };
+
+            match Regex::new(&expression) {
+                Ok(regex) => regexes.push(regex),
+                Err(e) => return Err(TipupError::from(format!("failed to compile measurement_class pattern '{}': {}", pattern, e))),
+            }
+        }
+
+        Ok(
+            ClassMatcher {
+                patterns: regexes,
+            }
+        )
+    }
+
+    pub fn matches(&self, measurement_class: &str) -> bool {
+        self.patterns.iter().any(|x| x.is_match(measurement_class))
+    }
+}
+
+struct PipeEntry {
+    measurement_class: ClassMatcher,
+    filter: Option<Rule>,
+    analyzer: Arc<Mutex<Box<Analyzer>>>,
+    queue_tx: Option<Sender<Arc<OrderedDocument>>>,
+    statistics: Arc<WorkerStatistics>,
+    health: Arc<Mutex<AnalyzerHealth>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+//disables an analyzer after error_threshold consecutive failures and retries it after
+//disable_seconds, analyzers that panic stay disabled since their state may be inconsistent
+#[derive(Clone, Copy)]
+pub struct CircuitBreaker {
+    pub error_threshold: usize,
+    pub disable_seconds: i64,
+}
+
+//failures of a single analyzer, written to the 'health' field of its analyzers document
+struct AnalyzerHealth {
+    errors: i64,
+    consecutive_errors: usize,
+    last_error: Option<String>,
+    last_error_operation: Option<String>,
+    last_error_measurement_id: Option<Bson>,
+    last_error_timestamp: Option<i64>,
+    disabled_until: Option<i64>,
+    panicked: bool,
+    paused: bool,
+    changed: bool,
+}
+
+//counters shared between the pipe and an analyzer worker
+struct WorkerStatistics {
+    queue_depth: AtomicUsize,
+    processed: AtomicUsize,
+    latency_total_us: AtomicUsize,
+    latency_max_us: AtomicUsize,
+}
+
+impl AnalyzerHealth {
+    fn new() -> AnalyzerHealth {
+        AnalyzerHealth {
+            errors: 0,
+            consecutive_errors: 0,
+            last_error: None,
+            last_error_operation: None,
+            last_error_measurement_id: None,
+            last_error_timestamp: None,
+            disabled_until: None,
+            panicked: false,
+            paused: false,
+            changed: true,
+        }
+    }
+
+    fn state(&self) -> &'static str {
+        match (self.panicked, self.disabled_until, self.paused) {
+            (true, _, _) => "failed",
+            (false, Some(_), _) => "disabled",
+            (false, None, true) => "paused",
+            (false, None, false) => "active",
+        }
+    }
+
+    fn enabled(&mut self, name: &str, breaker: &CircuitBreaker, now: i64) -> bool {
+        if self.panicked {
+            return false;
+        }
+
+        match self.disabled_until {
+            Some(disabled_until) if now < disabled_until => false,
+            Some(_) => {
+                //half open, the next failure disables the analyzer again
+                info!("re-enabling analyzer '{}'", name);
+                self.disabled_until = None;
+                self.consecutive_errors = breaker.error_threshold - 1;
+                self.changed = true;
+                true
+            },
+            None => true,
+        }
+    }
+
+    fn record_success(&mut self) {
+        self.consecutive_errors = 0;
+    }
+
+    fn record_error(&mut self, name: &str, breaker: &CircuitBreaker, operation: &str, measurement_id: Option<&Bson>, error: String, panicked: bool) {
+        let now = time::now_utc().to_timespec().sec;
+        self.errors += 1;
+        self.consecutive_errors += 1;
+        self.last_error = Some(error);
+        self.last_error_operation = Some(operation.to_owned());
+        self.last_error_measurement_id = measurement_id.cloned();
+        self.last_error_timestamp = Some(now);
+        self.changed = true;
+
+        if panicked {
+            error!("analyzer '{}' panicked, disabling until restart", name);
+            self.panicked = true;
+        } else if self.disabled_until.is_none() && self.consecutive_errors >= breaker.error_threshold {
+            error!("analyzer '{}' failed {} consecutive times, disabling for {} seconds", name, self.consecutive_errors, breaker.disable_seconds);
+            self.disabled_until = Some(now + breaker.disable_seconds);
+        }
+    }
+
+    fn to_document(&self) -> OrderedDocument {
+        let mut document = doc!(
+            "state" => (self.state()),
+            "errors" => (self.errors),
+            "consecutive_errors" => (self.consecutive_errors as i64),
+            "timestamp" => (time::now_utc().to_timespec().sec)
+        );
+
+        if let Some(ref last_error) = self.last_error {
+            document.insert("last_error", last_error.to_owned());
+        }
+
+        if let Some(ref last_error_operation) = self.last_error_operation {
+            document.insert("last_error_operation", last_error_operation.to_owned());
+        }
+
+        if let Some(ref last_error_measurement_id) = self.last_error_measurement_id {
+            document.insert("last_error_measurement_id", last_error_measurement_id.clone());
+        }
+
+        if let Some(last_error_timestamp) = self.last_error_timestamp {
+            document.insert("last_error_timestamp", last_error_timestamp);
+        }
+
+        if let Some(disabled_until) = self.disabled_until {
+            document.insert("disabled_until", disabled_until);
+        }
+
+        document
+    }
+}
+
+//per-analyzer worker statistics since the previous call to statistics
+pub struct AnalyzerStatistics {
+    pub name: String,
+    pub queue_depth: usize,
+    pub processed: usize,
+    pub mean_latency_ms: f64,
+    pub max_latency_ms: f64,
+}
+
+//every analyzer runs on its own worker thread fed by a bounded fifo queue, so a failing analyzer
+//only delays itself and measurements stay ordered per (vantage, domain)
 pub struct Pipe {
-    analyzers: Arc<Mutex<HashMap<String, HashMap<String, Box<Analyzer>>>>>,
+    analyzers: Arc<Mutex<HashMap<String, PipeEntry>>>,
+    result_window: Arc<RwLock<ResultWindow>>,
+    breaker: CircuitBreaker,
 }
 
 impl Pipe {
-    pub fn new() -> Pipe {
+    pub fn new(result_window: Arc<RwLock<ResultWindow>>, breaker: CircuitBreaker) -> Pipe {
         Pipe {
             analyzers: Arc::new(Mutex::new(HashMap::new())),
+            result_window: result_window,
+            breaker: breaker,
         }
     }
 
-    pub fn add_analyzer(&mut self, name: String, measurement_class: String, analyzer: Box<Analyzer>) -> Result<(), TipupError> {
+    pub fn add_analyzer(&mut self, name: String, measurement_class: ClassMatcher, filter: Option<Rule>, analyzer: Box<Analyzer>) -> Result<(), TipupError> {
         let mut analyzers = self.analyzers.lock().unwrap();
-        let mut analyzers = analyzers.entry(measurement_class).or_insert(HashMap::new());
         if analyzers.contains_key(&name) {
-            return Err(TipupError::from("analyzer name already exists"));
+            return Err(TipupError::from(format!("analyzer name '{}' already exists", name)));
+        }
+
+        //start analyzer worker
+        let analyzer = Arc::new(Mutex::new(analyzer));
+        let statistics = Arc::new(WorkerStatistics {
+            queue_depth: AtomicUsize::new(0),
+            processed: AtomicUsize::new(0),
+            latency_total_us: AtomicUsize::new(0),
+            latency_max_us: AtomicUsize::new(0),
+        });
+
+        let health = Arc::new(Mutex::new(AnalyzerHealth::new()));
+        let (queue_tx, queue_rx) = chan::sync::<Arc<OrderedDocument>>(QUEUE_LENGTH);
+        let (thread_name, thread_analyzer, thread_statistics, thread_health) = (name.clone(), analyzer.clone(), statistics.clone(), health.clone());
+        let (result_window, breaker) = (self.result_window.clone(), self.breaker);
+        let handle = try!(thread::Builder::new().name(format!("analyzer-{}", name)).spawn(move || {
+            for document in queue_rx {
+                process_measurement(&thread_name, &thread_analyzer, &thread_health, &breaker, &result_window, &thread_statistics, &document);
+            }
+        }).map_err(|e| TipupError::from(format!("failed to start worker for analyzer '{}': {}", name, e))));
+
+        analyzers.insert(name, PipeEntry {
+            measurement_class: measurement_class,
+            filter: filter,
+            analyzer: analyzer,
+            queue_tx: Some(queue_tx),
+            statistics: statistics,
+            health: health,
+            handle: Some(handle),
+        });
+
+        Ok(())
+    }
+
+    //a paused analyzer keeps its state and windows current but does not process measurements or ticks
+    pub fn set_paused(&self, name: &str, paused: bool) -> Result<(), TipupError> {
+        let analyzers = self.analyzers.lock().unwrap();
+        let entry = match analyzers.get(name) {
+            Some(entry) => entry,
+            None => return Err(TipupError::from(format!("analyzer '{}' does not exist", name))),
+        };
+
+        let mut health = entry.health.lock().unwrap();
+        if health.paused != paused {
+            health.paused = paused;
+            health.changed = true;
+        }
+
+        Ok(())
+    }
+
+    //stops the analyzer once its queue is processed and flushes its pending flags
+    pub fn remove_analyzer(&mut self, name: &str) -> Result<(), TipupError> {
+        let mut entry = match self.analyzers.lock().unwrap().remove(name) {
+            Some(entry) => entry,
+            None => return Err(TipupError::from(format!("analyzer '{}' does not exist", name))),
+        };
+
+        entry.queue_tx = None;
+        if let Some(handle) = entry.handle.take() {
+            if let Err(e) = handle.join() {
+                error!("worker for analyzer '{}' panicked: {:?}", name, e);
+            }
+        }
+
+        entry.run(name, &self.breaker, "flush", None, |analyzer| analyzer.flush());
+        Ok(())
+    }
+
+    //swaps in the analyzer built by f, which is given the current analyzer, once queued
+    //measurements are processed. The current analyzer is kept if f fails.
+    pub fn reconfigure_analyzer<F>(&mut self, name: &str, measurement_class: ClassMatcher, filter: Option<Rule>, f: F) -> Result<(), TipupError>
+            where F: FnOnce(&mut Analyzer) -> Result<Box<Analyzer>, TipupError> {
+        self.drain();
+        let mut analyzers = self.analyzers.lock().unwrap();
+        let entry = match analyzers.get_mut(name) {
+            Some(entry) => entry,
+            None => return Err(TipupError::from(format!("analyzer '{}' does not exist", name))),
+        };
+
+        {
+            //holding the lock keeps the worker from processing until the swap completes
+            let mut analyzer = entry.analyzer.lock().unwrap_or_else(|e| e.into_inner());
+            let replacement = try!(f(&mut **analyzer));
+            *analyzer = replacement;
         }
 
-        analyzers.insert(name, analyzer);
+        entry.measurement_class = measurement_class;
+        entry.filter = filter;
+
+        //the replacement starts with a clean record
+        let mut health = entry.health.lock().unwrap();
+        let paused = health.paused;
+        *health = AnalyzerHealth::new();
+        health.paused = paused;
         Ok(())
     }
 
@@ -36,14 +357,266 @@ impl Pipe {
             _ => return Err(TipupError::from("failed to parse result measurement_class")),
         };
 
-        //send to analyzers registered to that measurement
-        let mut analyzers = self.analyzers.lock().unwrap();
-        if analyzers.contains_key(measurement_class) {
-            for analyzer in analyzers.get_mut(measurement_class).unwrap().values_mut() {
-                try!(analyzer.process_measurement(document));
+        //queue for analyzers registered to that measurement and accepting the document
+        let document = Arc::new(document.clone());
+        let analyzers = self.analyzers.lock().unwrap();
+        for entry in analyzers.values() {
+            if !entry.accepts(measurement_class, &document) {
+                continue;
+            }
+
+            //blocks while the analyzer's queue is full
+            if let Some(ref queue_tx) = entry.queue_tx {
+                entry.statistics.queue_depth.fetch_add(1, AtomicOrdering::SeqCst);
+                queue_tx.send(document.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    //blocks until every queued measurement has been processed
+    pub fn drain(&self) {
+        loop {
+            let queued: usize = {
+                let analyzers = self.analyzers.lock().unwrap();
+                analyzers.values().map(|x| x.statistics.queue_depth.load(AtomicOrdering::SeqCst)).sum()
+            };
+
+            if queued == 0 {
+                return;
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    pub fn statistics(&self) -> Vec<AnalyzerStatistics> {
+        let analyzers = self.analyzers.lock().unwrap();
+        let mut statistics = Vec::new();
+        for (name, entry) in analyzers.iter() {
+            let processed = entry.statistics.processed.swap(0, AtomicOrdering::SeqCst);
+            let latency_total_us = entry.statistics.latency_total_us.swap(0, AtomicOrdering::SeqCst);
+            let latency_max_us = entry.statistics.latency_max_us.swap(0, AtomicOrdering::SeqCst);
+            statistics.push(AnalyzerStatistics {
+                name: name.to_owned(),
+                queue_depth: entry.statistics.queue_depth.load(AtomicOrdering::SeqCst),
+                processed: processed,
+                mean_latency_ms: match processed {
+                    0 => 0.0,
+                    _ => latency_total_us as f64 / processed as f64 / 1000.0,
+                },
+                max_latency_ms: latency_max_us as f64 / 1000.0,
+            });
+        }
+
+        statistics.sort_by(|a, b| a.name.cmp(&b.name));
+        statistics
+    }
+
+    pub fn initialize_analyzer(&self, db: &Database, name: &str) -> Result<(), TipupError> {
+        let analyzers = self.analyzers.lock().unwrap();
+        let entry = match analyzers.get(name) {
+            Some(entry) => entry,
+            None => return Err(TipupError::from(format!("analyzer '{}' does not exist", name))),
+        };
+
+        //restore from the latest snapshot if one exists, then initialize what it does not hold
+        let search_document = Some(doc!("analyzer" => name));
+        let document = try!(db.collection("analyzer_snapshots").find_one(search_document, None));
+        if let Some(document) = document {
+            match document.get("snapshot") {
+                Some(&Bson::Document(ref snapshot)) => {
+                    info!("restoring analyzer '{}' from snapshot", name);
+                    entry.run(name, &self.breaker, "restore", None, |analyzer| analyzer.restore(snapshot));
+                },
+                _ => return Err(TipupError::from(format!("failed to parse 'snapshot' document for analyzer '{}'", name))),
+            }
+        }
+
+        entry.run(name, &self.breaker, "initialize", None, |analyzer| analyzer.initialize(db));
+        Ok(())
+    }
+
+    pub fn snapshot(&self, db: &Database) -> Result<(), TipupError> {
+        //snapshots include every measurement sent so far, analyzers that fail to snapshot keep
+        //their previous one and have the failure recorded in their health
+        self.drain();
+        let analyzers = self.analyzers.lock().unwrap();
+        for (name, entry) in analyzers.iter() {
+            let snapshot = match entry.run(name, &self.breaker, "snapshot", None, |analyzer| analyzer.snapshot()) {
+                Some(Some(snapshot)) => snapshot,
+                _ => continue,
+            };
+
+            //upsert analyzer snapshot
+            let search_document = doc!("analyzer" => name);
+            let timestamp = time::now_utc().to_timespec().sec;
+            let snapshot_document = doc!("analyzer" => name, "timestamp" => timestamp, "snapshot" => snapshot);
+            let update_options = Some(FindOneAndUpdateOptions {
+                return_document: None,
+                max_time_ms: None,
+                projection: None,
+                sort: None,
+                upsert: Some(true),
+                write_concern: None,
+            });
+
+            //a failed write counts against that analyzer alone, the others and the checkpoints are still saved
+            if let Err(e) = db.collection("analyzer_snapshots").find_one_and_replace(search_document, snapshot_document, update_options) {
+                error!("failed to write snapshot of analyzer '{}': {}", name, e);
+                entry.health.lock().unwrap().record_error(name, &self.breaker, "snapshot", None, format!("failed to write snapshot: {}", e), false);
+            }
+        }
+
+        Ok(())
+    }
+
+    //writes the health of analyzers that changed since the last call to the analyzers collection
+    pub fn write_health(&self, db: &Database) -> Result<(), TipupError> {
+        let analyzers = self.analyzers.lock().unwrap();
+        for (name, entry) in analyzers.iter() {
+            let health_document = {
+                let mut health = entry.health.lock().unwrap();
+                if !health.changed {
+                    continue;
+                }
+
+                health.changed = false;
+                health.to_document()
+            };
+
+            let search_document = doc!("name" => name);
+            let set_document = doc!("health" => health_document);
+            let update_document = doc!("$set" => set_document);
+            let update_options = Some(UpdateOptions {
+                upsert: Some(false),
+                write_concern: None,
+            });
+
+            try!(db.collection("analyzers").update_one(search_document, update_document, update_options));
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<(), TipupError> {
+        //pending flags can only be emitted once queued measurements are processed
+        self.drain();
+        let analyzers = self.analyzers.lock().unwrap();
+        for (name, entry) in analyzers.iter() {
+            if !entry.paused() {
+                entry.run(name, &self.breaker, "flush", None, |analyzer| analyzer.flush());
             }
         }
 
         Ok(())
     }
+
+    //ticks see every measurement sent before them, so a vantage whose measurements are still
+    //queued is not reported as silent
+    pub fn tick(&self, now: i64) -> Result<(), TipupError> {
+        self.drain();
+        let analyzers = self.analyzers.lock().unwrap();
+        for (name, entry) in analyzers.iter() {
+            if !entry.paused() {
+                entry.run(name, &self.breaker, "tick", None, |analyzer| analyzer.tick(now));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PipeEntry {
+    fn run<T, F>(&self, name: &str, breaker: &CircuitBreaker, operation: &str, measurement_id: Option<&Bson>, f: F) -> Option<T>
+            where F: FnOnce(&mut Analyzer) -> Result<T, TipupError> {
+        run_analyzer(name, &self.analyzer, &self.health, breaker, operation, measurement_id, f)
+    }
+
+    fn paused(&self) -> bool {
+        self.health.lock().unwrap().paused
+    }
+
+    fn accepts(&self, measurement_class: &str, document: &OrderedDocument) -> bool {
+        self.measurement_class.matches(measurement_class) && match self.filter {
+            Some(ref filter) => filter.evaluate(document),
+            None => true,
+        }
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        //closing the queues stops the workers once they finish queued measurements
+        let mut analyzers = self.analyzers.lock().unwrap();
+        for entry in analyzers.values_mut() {
+            entry.queue_tx = None;
+        }
+
+        for (name, entry) in analyzers.iter_mut() {
+            if let Some(handle) = entry.handle.take() {
+                if let Err(e) = handle.join() {
+                    error!("worker for analyzer '{}' panicked: {:?}", name, e);
+                }
+            }
+        }
+    }
+}
+
+fn process_measurement(name: &str, analyzer: &Mutex<Box<Analyzer>>, health: &Mutex<AnalyzerHealth>, breaker: &CircuitBreaker,
+        result_window: &RwLock<ResultWindow>, statistics: &WorkerStatistics, document: &OrderedDocument) {
+    let start = time::precise_time_ns();
+    let paused = health.lock().unwrap().paused;
+    if !paused {
+        run_analyzer(name, analyzer, health, breaker, "process_measurement", document.get("_id"), |analyzer| analyzer.process_measurement(document));
+    }
+
+    //windows are updated after the analyzer so it compares against previous measurements only,
+    //and while it is paused or disabled so they are current when it resumes
+    if let Err(e) = result_window.read().unwrap().add_result(name, document) {
+        error!("failed to add measurement to result window of analyzer '{}': {}", name, e);
+    }
+
+    let latency_us = ((time::precise_time_ns() - start) / 1000) as usize;
+    statistics.latency_total_us.fetch_add(latency_us, AtomicOrdering::SeqCst);
+    statistics.latency_max_us.fetch_max(latency_us, AtomicOrdering::SeqCst);
+    statistics.processed.fetch_add(1, AtomicOrdering::SeqCst);
+    statistics.queue_depth.fetch_sub(1, AtomicOrdering::SeqCst);
+}
+
+//runs f against the analyzer unless the circuit breaker disabled it, recording any error or panic
+fn run_analyzer<T, F>(name: &str, analyzer: &Mutex<Box<Analyzer>>, health: &Mutex<AnalyzerHealth>, breaker: &CircuitBreaker,
+        operation: &str, measurement_id: Option<&Bson>, f: F) -> Option<T> where F: FnOnce(&mut Analyzer) -> Result<T, TipupError> {
+    if !health.lock().unwrap().enabled(name, breaker, time::now_utc().to_timespec().sec) {
+        return None;
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut analyzer = analyzer.lock().unwrap();
+        f(&mut **analyzer)
+    }));
+
+    let mut health = health.lock().unwrap();
+    match result {
+        Ok(Ok(value)) => {
+            health.record_success();
+            Some(value)
+        },
+        Ok(Err(e)) => {
+            error!("analyzer '{}' failed {} measurement_id:{:?}: {}", name, operation, measurement_id, e);
+            health.record_error(name, breaker, operation, measurement_id, format!("{}", e), false);
+            None
+        },
+        Err(payload) => {
+            let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+                (Some(message), _) => message.to_string(),
+                (None, Some(message)) => message.to_owned(),
+                (None, None) => "unknown panic".to_owned(),
+            };
+
+            health.record_error(name, breaker, operation, measurement_id, format!("panic: {}", message), true);
+            None
+        },
+    }
 }
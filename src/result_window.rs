@@ -1,5 +1,8 @@
-use bson::Bson;
+use bson::{self, Bson, Document};
 use bson::ordered::OrderedDocument;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use mongodb::coll::options::FindOptions;
 use mongodb::db::{Database, ThreadedDatabase};
 use time;
 
@@ -8,65 +11,151 @@ use error::TipupError;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
+//windows belong to the analyzer that registered them and are fed only the measurements it
+//accepts, after processing. windows retired on reconfiguration are reused by registrations
+//tracking the same field, so a rebuilt analyzer keeps its history
 pub struct ResultWindow {
+    analyzer_windows: HashMap<String, AnalyzerWindows>,
+    retired_windows: HashMap<String, AnalyzerWindows>,
+}
+
+#[derive(Default)]
+struct AnalyzerWindows {
     variable_windows: Vec<Arc<RwLock<VariableWindow>>>,
+    content_windows: Vec<Arc<RwLock<ContentWindow>>>,
+    seasonal_windows: Vec<Arc<RwLock<SeasonalWindow>>>,
+    measurement_view: Option<Arc<RwLock<MeasurementView>>>,
+    change_point_window: Option<Arc<RwLock<ChangePointWindow>>>,
 }
 
 impl ResultWindow {
     pub fn new() -> ResultWindow {
         ResultWindow {
-            variable_windows: Vec::new(),
+            analyzer_windows: HashMap::new(),
+            retired_windows: HashMap::new(),
         }
     }
 
-    pub fn register_variable(&mut self, variable_name: &Vec<String>) -> Result<Arc<RwLock<VariableWindow>>, TipupError> {
-        //check if variable_name already exists
-        for variable_window in self.variable_windows.iter() {
-            {
-                let variable_window_clone = variable_window.read().unwrap();
-                if variable_window_clone.variable_name_equals(&variable_name) {
-                    return Ok(variable_window.clone());
-                }
-            }
+    pub fn register_change_point(&mut self, analyzer_name: &str) -> Result<Arc<RwLock<ChangePointWindow>>, TipupError> {
+        //change point state is owned by a single analyzer
+        let retired = self.retired_windows.get_mut(analyzer_name).and_then(|x| x.change_point_window.take());
+        let windows = self.analyzer_windows.entry(analyzer_name.to_owned()).or_insert(AnalyzerWindows::default());
+        if windows.change_point_window.is_none() {
+            windows.change_point_window = Some(retired.unwrap_or(Arc::new(RwLock::new(ChangePointWindow::new()))));
         }
 
-        //create new variable window
-        let variable_window = Arc::new(RwLock::new(VariableWindow::new(variable_name.to_owned())));
-        self.variable_windows.push(variable_window.clone());
+        Ok(windows.change_point_window.as_ref().unwrap().clone())
+    }
+
+    pub fn register_content(&mut self, analyzer_name: &str, body_field: &Vec<String>, window_length: usize) -> Result<Arc<RwLock<ContentWindow>>, TipupError> {
+        let retired = self.retired_windows.get_mut(analyzer_name)
+            .and_then(|x| take_window(&mut x.content_windows, |w| &w.body_field == body_field));
+        let content_window = match retired {
+            Some(content_window) => {
+                content_window.write().unwrap().resize(window_length);
+                content_window
+            },
+            None => Arc::new(RwLock::new(ContentWindow::new(body_field.to_owned(), window_length))),
+        };
+
+        let windows = self.analyzer_windows.entry(analyzer_name.to_owned()).or_insert(AnalyzerWindows::default());
+        windows.content_windows.push(content_window.clone());
+        Ok(content_window)
+    }
+
+    pub fn register_measurement_view(&mut self, analyzer_name: &str) -> Result<Arc<RwLock<MeasurementView>>, TipupError> {
+        let retired = self.retired_windows.get_mut(analyzer_name).and_then(|x| x.measurement_view.take());
+        let windows = self.analyzer_windows.entry(analyzer_name.to_owned()).or_insert(AnalyzerWindows::default());
+        if windows.measurement_view.is_none() {
+            windows.measurement_view = Some(retired.unwrap_or(Arc::new(RwLock::new(MeasurementView::new()))));
+        }
+
+        Ok(windows.measurement_view.as_ref().unwrap().clone())
+    }
+
+    pub fn register_seasonal(&mut self, analyzer_name: &str, variable_name: &Vec<String>, history_seconds: i64, bucket_length: usize) -> Result<Arc<RwLock<SeasonalWindow>>, TipupError> {
+        let retired = self.retired_windows.get_mut(analyzer_name)
+            .and_then(|x| take_window(&mut x.seasonal_windows, |w| &w.variable_name == variable_name && w.bucket_length == bucket_length));
+        let seasonal_window = retired.unwrap_or(Arc::new(RwLock::new(SeasonalWindow::new(variable_name.to_owned(), history_seconds, bucket_length))));
+
+        let windows = self.analyzer_windows.entry(analyzer_name.to_owned()).or_insert(AnalyzerWindows::default());
+        windows.seasonal_windows.push(seasonal_window.clone());
+        Ok(seasonal_window)
+    }
+
+    pub fn register_variable(&mut self, analyzer_name: &str, variable_name: &Vec<String>, window_length: usize, history_seconds: i64) -> Result<Arc<RwLock<VariableWindow>>, TipupError> {
+        let retired = self.retired_windows.get_mut(analyzer_name)
+            .and_then(|x| take_window(&mut x.variable_windows, |w| &w.variable_name == variable_name));
+        let variable_window = match retired {
+            Some(variable_window) => {
+                variable_window.write().unwrap().resize(window_length);
+                variable_window
+            },
+            None => Arc::new(RwLock::new(VariableWindow::new(variable_name.to_owned(), window_length, history_seconds))),
+        };
+
+        let windows = self.analyzer_windows.entry(analyzer_name.to_owned()).or_insert(AnalyzerWindows::default());
+        windows.variable_windows.push(variable_window.clone());
         Ok(variable_window)
     }
 
-    pub fn initialize(&mut self, proddle_db: &Database) -> Result<(), TipupError> {
-        for variable_window in self.variable_windows.iter() {
-            {
-                let mut variable_window = variable_window.write().unwrap();
-                try!(variable_window.initialize(proddle_db));
-            }
+    //stops feeding the analyzer's windows and keeps them for reuse by its next registrations
+    pub fn retire(&mut self, analyzer_name: &str) {
+        if let Some(windows) = self.analyzer_windows.remove(analyzer_name) {
+            self.retired_windows.insert(analyzer_name.to_owned(), windows);
         }
+    }
 
-        Ok(())
+    //drops retired windows that were not reused
+    pub fn release(&mut self, analyzer_name: &str) {
+        self.retired_windows.remove(analyzer_name);
     }
 
-    pub fn add_result(&mut self, document: OrderedDocument) -> Result<(), TipupError> {
+    //undoes registrations made since retire, reactivating the retired windows
+    pub fn reinstate(&mut self, analyzer_name: &str) {
+        self.analyzer_windows.remove(analyzer_name);
+        if let Some(windows) = self.retired_windows.remove(analyzer_name) {
+            self.analyzer_windows.insert(analyzer_name.to_owned(), windows);
+        }
+    }
+
+    pub fn add_result(&self, analyzer_name: &str, document: &OrderedDocument) -> Result<(), TipupError> {
+        let windows = match self.analyzer_windows.get(analyzer_name) {
+            Some(windows) => windows,
+            None => return Ok(()),
+        };
+
         //parse hostname and domain
-        let (hostname, domain);
-        {
-            hostname = match document.get("vantage_hostname") {
-                Some(&Bson::String(ref hostname)) => hostname.to_owned(),
-                _ => return Err(TipupError::from("failed to parse vanage_hostname from _id document")),
-            };
+        let hostname = match document.get("vantage_hostname") {
+            Some(&Bson::String(ref hostname)) => hostname.to_owned(),
+            _ => return Err(TipupError::from("failed to parse vanage_hostname from _id document")),
+        };
+
+        let domain = match document.get("measurement_domain") {
+            Some(&Bson::String(ref url)) => url.to_owned(),
+            _ => return Err(TipupError::from("failed to parse measurement_domain from _id document")),
+        };
+
+        //add document to the analyzer's windows
+        for variable_window in windows.variable_windows.iter() {
+            let mut variable_window = variable_window.write().unwrap();
+            try!(variable_window.add_result(&hostname, &domain, document));
+        }
 
-            domain = match document.get("measurement_domain") {
-                Some(&Bson::String(ref url)) => url.to_owned(),
-                _ => return Err(TipupError::from("failed to parse measurement_domain from _id document")),
-            };
+        for content_window in windows.content_windows.iter() {
+            let mut content_window = content_window.write().unwrap();
+            content_window.add_result(&hostname, &domain, document);
+        }
+
+        if let Some(ref measurement_view) = windows.measurement_view {
+            let mut measurement_view = measurement_view.write().unwrap();
+            try!(measurement_view.add_result(&hostname, &domain, document));
         }
 
-        //add document to variable windows
-        for variable_window in self.variable_windows.iter() {
-            {
-                let mut variable_window = variable_window.write().unwrap();
-                try!(variable_window.add_result(&hostname, &domain, &document));
+        if let Some(&Bson::I64(timestamp)) = document.get("timestamp") {
+            for seasonal_window in windows.seasonal_windows.iter() {
+                let mut seasonal_window = seasonal_window.write().unwrap();
+                seasonal_window.add_result(&hostname, &domain, timestamp, document);
             }
         }
 
@@ -74,28 +163,51 @@ impl ResultWindow {
     }
 }
 
+fn take_window<T, F>(windows: &mut Vec<Arc<RwLock<T>>>, compatible: F) -> Option<Arc<RwLock<T>>> where F: Fn(&T) -> bool {
+    match windows.iter().position(|x| compatible(&x.read().unwrap())) {
+        Some(index) => Some(windows.remove(index)),
+        None => None,
+    }
+}
+
 pub struct VariableWindow {
     variable_name: Vec<String>,
+    window_length: usize,
+    history_seconds: i64,
+    initialized: bool,
     values: HashMap<String, HashMap<String, Vec<f64>>>,
 }
 
 impl VariableWindow {
-    fn new(variable_name: Vec<String>) -> VariableWindow {
+    fn new(variable_name: Vec<String>, window_length: usize, history_seconds: i64) -> VariableWindow {
         VariableWindow {
             variable_name: variable_name,
+            window_length: window_length,
+            history_seconds: history_seconds,
+            initialized: false,
             values: HashMap::new(),
         }
     }
 
-    fn initialize(&mut self, proddle_db: &Database) -> Result<(), TipupError> {
-        let start_time = time::now_utc().to_timespec().sec - (60 * 60 * 24 * 5);
+    pub fn initialize(&mut self, proddle_db: &Database) -> Result<(), TipupError> {
+        //only preload once
+        if self.initialized {
+            return Ok(());
+        }
+
+        //preload the variable for each vantage and domain in timestamp order
+        let start_time = time::now_utc().to_timespec().sec - self.history_seconds;
         let timestamp_gte = doc!("$gte" => start_time);
-        let match_doc = doc!("measurement_class" => "HttpGet", "timestamp" => timestamp_gte);
-        let id_doc = doc!("vantage_hostname" => "$hostname", "domain" => "$domain");
-        let values_doc = doc!("$push" => "$total_time");
+        let exists = doc!("$exists" => true);
+        let mut match_doc = doc!("timestamp" => timestamp_gte);
+        match_doc.insert(self.variable_name.join("."), exists);
+        let sort_doc = doc!("timestamp" => 1);
+        let id_doc = doc!("vantage_hostname" => "$vantage_hostname", "domain" => "$measurement_domain");
+        let values_doc = doc!("$push" => (format!("${}", self.variable_name.join("."))));
         let group_doc = doc!("_id" => id_doc, "values" => values_doc);
         let aggregate_doc = vec!(
             doc!("$match" => match_doc),
+            doc!("$sort" => sort_doc),
             doc!("$group" => group_doc),
         );
 
@@ -118,7 +230,7 @@ impl VariableWindow {
                 _ => continue,
             };
 
-            let values = match document.get("values") {
+            let mut values = match document.get("values") {
                 Some(&Bson::Array(ref array)) => {
                     let mut values = Vec::new();
                     for value in array {
@@ -135,18 +247,36 @@ impl VariableWindow {
                 _ => continue,
             };
 
-            //insert values into variable values map
+            //insert most recent values into variable values map
+            if values.len() > self.window_length {
+                let excess = values.len() - self.window_length;
+                values.drain(0..excess);
+            }
+
             self.values.entry(hostname).or_insert(HashMap::new()).insert(domain, values);
         }
 
+        self.initialized = true;
         Ok(())
     }
 
+    fn resize(&mut self, window_length: usize) {
+        self.window_length = window_length;
+        for domain_map in self.values.values_mut() {
+            for values in domain_map.values_mut() {
+                if values.len() > window_length {
+                    let excess = values.len() - window_length;
+                    values.drain(0..excess);
+                }
+            }
+        }
+    }
+
     fn add_result(&mut self, hostname: &str, domain: &str, document: &OrderedDocument) -> Result<(), TipupError> {
         if let Some(value) = get_value(&self.variable_name, document) {
             let values = self.values.entry(hostname.to_owned()).or_insert(HashMap::new()).entry(domain.to_owned()).or_insert(Vec::new());
             values.push(value);
-            if values.len() > 10 {
+            while values.len() > self.window_length {
                 values.remove(0);
             }
         }
@@ -163,25 +293,451 @@ impl VariableWindow {
 
         None
     }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ContentFeatures {
+    pub vantage_hostname: String,
+    pub size: usize,
+    pub sha256: String,
+    pub simhash: String,
+}
+
+impl ContentFeatures {
+    pub fn new(hostname: &str, body: &str) -> ContentFeatures {
+        let mut hasher = Sha256::new();
+        hasher.input_str(body);
+
+        //simhash is stored as hex since bson has no unsigned 64 bit integer
+        ContentFeatures {
+            vantage_hostname: hostname.to_owned(),
+            size: body.len(),
+            sha256: hasher.result_str(),
+            simhash: format!("{:016x}", simhash(body)),
+        }
+    }
+
+    pub fn simhash_distance(&self, other: &ContentFeatures) -> Option<u32> {
+        match (u64::from_str_radix(&self.simhash, 16), u64::from_str_radix(&other.simhash, 16)) {
+            (Ok(x), Ok(y)) => Some((x ^ y).count_ones()),
+            _ => None,
+        }
+    }
+}
+
+pub struct ContentWindow {
+    body_field: Vec<String>,
+    window_length: usize,
+    features: HashMap<String, Vec<ContentFeatures>>,
+    held: HashMap<String, Vec<ContentFeatures>>,
+    flagged: Option<Bson>,
+}
+
+impl ContentWindow {
+    fn new(body_field: Vec<String>, window_length: usize) -> ContentWindow {
+        ContentWindow {
+            body_field: body_field,
+            window_length: window_length,
+            features: HashMap::new(),
+            held: HashMap::new(),
+            flagged: None,
+        }
+    }
+
+    fn resize(&mut self, window_length: usize) {
+        self.window_length = window_length;
+        for features in self.features.values_mut().chain(self.held.values_mut()) {
+            if features.len() > window_length {
+                let excess = features.len() - window_length;
+                features.drain(0..excess);
+            }
+        }
+    }
+
+    //marks the measurement being processed as flagged so its body is held out of the baseline
+    pub fn set_flagged(&mut self, document: &OrderedDocument) {
+        self.flagged = document.get("_id").cloned();
+    }
+
+    fn add_result(&mut self, hostname: &str, domain: &str, document: &OrderedDocument) {
+        let flagged = self.flagged.is_some() && self.flagged.as_ref() == document.get("_id");
+        self.flagged = None;
+
+        //the baseline is per domain so every vantage contributes to it
+        let body = match get_field(&self.body_field, document) {
+            Some(&Bson::String(ref body)) => body,
+            _ => return,
+        };
+
+        //flagged bodies are held back, and only replace the baseline once window_length
+        //measurements in a row are flagged, ex. when the site itself changed
+        let features = if flagged {
+            let held = self.held.entry(domain.to_owned()).or_insert(Vec::new());
+            held.push(ContentFeatures::new(hostname, body));
+            if held.len() < self.window_length {
+                return;
+            }
+
+            let held = self.held.remove(domain).unwrap_or(Vec::new());
+            self.features.insert(domain.to_owned(), held);
+            return;
+        } else {
+            self.held.remove(domain);
+            self.features.entry(domain.to_owned()).or_insert(Vec::new())
+        };
+
+        features.push(ContentFeatures::new(hostname, body));
+        while features.len() > self.window_length {
+            features.remove(0);
+        }
+    }
+
+    pub fn get_features(&self, domain: &str) -> Option<&Vec<ContentFeatures>> {
+        self.features.get(domain)
+    }
+
+    pub fn snapshot(&self) -> Result<Document, TipupError> {
+        let entries: Vec<ContentEntry> = self.features.iter().map(|(domain, features)| ContentEntry {
+            measurement_domain: domain.to_owned(),
+            features: features.clone(),
+            held: self.held.get(domain).cloned().unwrap_or(Vec::new()),
+        }).collect();
+
+        match bson::to_bson(&entries) {
+            Ok(entries) => Ok(doc!("domains" => entries)),
+            Err(e) => Err(TipupError::from(format!("failed to encode content features: {}", e))),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        let entries: Vec<ContentEntry> = match snapshot.get("domains") {
+            Some(domains) => match bson::from_bson(domains.clone()) {
+                Ok(entries) => entries,
+                Err(e) => return Err(TipupError::from(format!("failed to decode content features: {}", e))),
+            },
+            None => return Err(TipupError::from("failed to parse 'domains' from content snapshot")),
+        };
+
+        for entry in entries {
+            if entry.held.len() > 0 {
+                self.held.insert(entry.measurement_domain.to_owned(), entry.held);
+            }
+
+            self.features.insert(entry.measurement_domain, entry.features);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ContentEntry {
+    measurement_domain: String,
+    features: Vec<ContentFeatures>,
+    #[serde(default)]
+    held: Vec<ContentFeatures>,
+}
+
+//64 bit simhash over lowercase words hashed with fnv-1a, which is stable across builds
+fn simhash(body: &str) -> u64 {
+    let mut weights = [0i64; 64];
+    for word in body.split(|c: char| !c.is_alphanumeric()).filter(|x| x.len() > 0) {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in word.to_lowercase().bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+
+        for i in 0..64 {
+            match (hash >> i) & 1 {
+                1 => weights[i] += 1,
+                _ => weights[i] -= 1,
+            }
+        }
+    }
+
+    let mut simhash = 0;
+    for i in 0..64 {
+        if weights[i] > 0 {
+            simhash |= 1 << i;
+        }
+    }
+
+    simhash
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ChangePointState {
+    pub count: usize,
+    pub baseline_mean: f64,
+    pub baseline_sum_squares: f64,
+    pub ewma: f64,
+    pub cusum_upper: f64,
+    pub cusum_lower: f64,
+    pub shifted: bool,
+    #[serde(default)]
+    pub shift_count: usize,
+    #[serde(default)]
+    pub shift_mean: f64,
+    #[serde(default)]
+    pub shift_sum_squares: f64,
+}
+
+impl ChangePointState {
+    fn new() -> ChangePointState {
+        ChangePointState {
+            count: 0,
+            baseline_mean: 0.0,
+            baseline_sum_squares: 0.0,
+            ewma: 0.0,
+            cusum_upper: 0.0,
+            cusum_lower: 0.0,
+            shifted: false,
+            shift_count: 0,
+            shift_mean: 0.0,
+            shift_sum_squares: 0.0,
+        }
+    }
+
+    //moves the learned baseline towards a value with an exponentially weighted update
+    pub fn adapt_baseline(&mut self, value: f64, baseline_lambda: f64) {
+        let delta = value - self.baseline_mean;
+        let variance = (self.baseline_sum_squares / (self.count - 1) as f64) + (baseline_lambda * delta * delta);
+        self.baseline_mean += baseline_lambda * delta;
+        self.baseline_sum_squares = (1.0 - baseline_lambda) * variance * (self.count - 1) as f64;
+    }
+
+    //learns the level of a shifted series, returns true once it has enough values to replace the baseline
+    pub fn learn_shift(&mut self, value: f64, warmup_samples: usize) -> bool {
+        self.shift_count += 1;
+        let delta = value - self.shift_mean;
+        self.shift_mean += delta / self.shift_count as f64;
+        self.shift_sum_squares += delta * (value - self.shift_mean);
+        self.shift_count >= warmup_samples
+    }
+
+    pub fn adopt_shift(&mut self) {
+        self.count = self.shift_count;
+        self.baseline_mean = self.shift_mean;
+        self.baseline_sum_squares = self.shift_sum_squares;
+        self.reset_shift();
+    }
+
+    pub fn reset_shift(&mut self) {
+        self.ewma = 0.0;
+        self.cusum_upper = 0.0;
+        self.cusum_lower = 0.0;
+        self.shifted = false;
+        self.shift_count = 0;
+        self.shift_mean = 0.0;
+        self.shift_sum_squares = 0.0;
+    }
+
+    pub fn baseline_std_dev(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+
+        (self.baseline_sum_squares / (self.count - 1) as f64).sqrt()
+    }
+}
+
+pub struct ChangePointWindow {
+    states: HashMap<String, HashMap<String, ChangePointState>>,
+}
+
+impl ChangePointWindow {
+    fn new() -> ChangePointWindow {
+        ChangePointWindow {
+            states: HashMap::new(),
+        }
+    }
+
+    pub fn get_state_mut(&mut self, hostname: &str, domain: &str) -> &mut ChangePointState {
+        self.states.entry(hostname.to_owned()).or_insert(HashMap::new())
+            .entry(domain.to_owned()).or_insert(ChangePointState::new())
+    }
+
+    pub fn snapshot(&self) -> Result<Document, TipupError> {
+        let mut entries = Vec::new();
+        for (hostname, domain_map) in self.states.iter() {
+            for (domain, state) in domain_map.iter() {
+                entries.push(ChangePointEntry {
+                    vantage_hostname: hostname.to_owned(),
+                    measurement_domain: domain.to_owned(),
+                    state: state.clone(),
+                });
+            }
+        }
+
+        match bson::to_bson(&entries) {
+            Ok(entries) => Ok(doc!("states" => entries)),
+            Err(e) => Err(TipupError::from(format!("failed to encode change point states: {}", e))),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Document) -> Result<(), TipupError> {
+        let entries: Vec<ChangePointEntry> = match snapshot.get("states") {
+            Some(states) => match bson::from_bson(states.clone()) {
+                Ok(entries) => entries,
+                Err(e) => return Err(TipupError::from(format!("failed to decode change point states: {}", e))),
+            },
+            None => return Err(TipupError::from("failed to parse 'states' from change point snapshot")),
+        };
+
+        for entry in entries {
+            self.states.entry(entry.vantage_hostname).or_insert(HashMap::new())
+                .insert(entry.measurement_domain, entry.state);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ChangePointEntry {
+    vantage_hostname: String,
+    measurement_domain: String,
+    state: ChangePointState,
+}
+
+pub struct MeasurementView {
+    measurements: HashMap<String, HashMap<String, HashMap<String, OrderedDocument>>>,
+}
+
+impl MeasurementView {
+    fn new() -> MeasurementView {
+        MeasurementView {
+            measurements: HashMap::new(),
+        }
+    }
+
+    fn add_result(&mut self, hostname: &str, domain: &str, document: &OrderedDocument) -> Result<(), TipupError> {
+        let measurement_class = match document.get("measurement_class") {
+            Some(&Bson::String(ref measurement_class)) => measurement_class.to_owned(),
+            _ => return Err(TipupError::from("failed to parse measurement_class from result document")),
+        };
+
+        //retain only the latest measurement from each vantage
+        self.measurements.entry(measurement_class).or_insert(HashMap::new())
+            .entry(domain.to_owned()).or_insert(HashMap::new())
+            .insert(hostname.to_owned(), document.clone());
+        Ok(())
+    }
+
+    pub fn get_measurements(&self, measurement_class: &str, domain: &str) -> Option<&HashMap<String, OrderedDocument>> {
+        if let Some(domain_map) = self.measurements.get(measurement_class) {
+            if let Some(measurements) = domain_map.get(domain) {
+                return Some(measurements);
+            }
+        }
+
+        None
+    }
+}
+
+//number of hour of week buckets
+pub const HOURS_PER_WEEK: usize = 168;
 
-    fn variable_name_equals(&self, variable_name: &Vec<String>) -> bool {
-        //check length
-        if self.variable_name.len() != variable_name.len() {
-            return false;
+pub struct SeasonalWindow {
+    variable_name: Vec<String>,
+    history_seconds: i64,
+    bucket_length: usize,
+    initialized: bool,
+    buckets: HashMap<String, HashMap<String, Vec<Vec<f64>>>>,
+}
+
+impl SeasonalWindow {
+    fn new(variable_name: Vec<String>, history_seconds: i64, bucket_length: usize) -> SeasonalWindow {
+        SeasonalWindow {
+            variable_name: variable_name,
+            history_seconds: history_seconds,
+            bucket_length: bucket_length,
+            initialized: false,
+            buckets: HashMap::new(),
         }
+    }
 
-        //check name
-        for i in 0..self.variable_name.len() {
-            if self.variable_name[i] != variable_name[i] {
-                return false;
+    pub fn initialize(&mut self, proddle_db: &Database) -> Result<(), TipupError> {
+        //only preload once
+        if self.initialized {
+            return Ok(());
+        }
+
+        //iterate over measurements within history containing the variable
+        let start_time = time::now_utc().to_timespec().sec - self.history_seconds;
+        let timestamp_gte = doc!("$gte" => start_time);
+        let exists = doc!("$exists" => true);
+        let mut search_document = doc!("timestamp" => timestamp_gte);
+        search_document.insert(self.variable_name.join("."), exists);
+
+        let mut projection = doc!("vantage_hostname" => 1, "measurement_domain" => 1, "timestamp" => 1);
+        projection.insert(self.variable_name.join("."), 1);
+        let sort = doc!("timestamp" => 1);
+        let mut find_options = FindOptions::new();
+        find_options.projection = Some(projection);
+        find_options.sort = Some(sort);
+
+        let cursor = try!(proddle_db.collection("measurements").find(Some(search_document), Some(find_options)));
+        for document in cursor {
+            let document = try!(document);
+
+            let (hostname, domain, timestamp) = match (document.get("vantage_hostname"), document.get("measurement_domain"), document.get("timestamp")) {
+                (Some(&Bson::String(ref hostname)), Some(&Bson::String(ref domain)), Some(&Bson::I64(timestamp))) => (hostname.to_owned(), domain.to_owned(), timestamp),
+                _ => continue,
+            };
+
+            self.add_result(&hostname, &domain, timestamp, &document);
+        }
+
+        self.initialized = true;
+        Ok(())
+    }
+
+    fn add_result(&mut self, hostname: &str, domain: &str, timestamp: i64, document: &OrderedDocument) {
+        if let Some(value) = get_value(&self.variable_name, document) {
+            let buckets = self.buckets.entry(hostname.to_owned()).or_insert(HashMap::new())
+                .entry(domain.to_owned()).or_insert(vec![Vec::new(); HOURS_PER_WEEK]);
+
+            let values = &mut buckets[hour_of_week(timestamp)];
+            values.push(value);
+            while values.len() > self.bucket_length {
+                values.remove(0);
+            }
+        }
+    }
+
+    pub fn get_buckets(&self, hostname: &str, domain: &str) -> Option<&Vec<Vec<f64>>> {
+        if let Some(domain_map) = self.buckets.get(hostname) {
+            if let Some(buckets) = domain_map.get(domain) {
+                return Some(buckets);
             }
         }
 
-        true
+        None
+    }
+}
+
+//hour of the week in utc, monday 00:00 is bucket 0 (the unix epoch fell on a thursday)
+pub fn hour_of_week(timestamp: i64) -> usize {
+    //euclidean division keeps timestamps before the epoch in range
+    (timestamp.div_euclid(3600) + (3 * 24)).rem_euclid(HOURS_PER_WEEK as i64) as usize
+}
+
+pub fn get_field<'a>(variable_name: &Vec<String>, document: &'a OrderedDocument) -> Option<&'a Bson> {
+    let mut index_document = document;
+    for (i, variable) in variable_name.iter().enumerate() {
+        match index_document.get(variable) {
+            Some(&Bson::Document(ref document)) if i + 1 < variable_name.len() => index_document = document,
+            Some(value) if i + 1 == variable_name.len() => return Some(value),
+            _ => return None,
+        }
     }
+
+    None
 }
 
-fn get_value(variable_name: &Vec<String>, document: &OrderedDocument) -> Option<f64> {
+pub fn get_value(variable_name: &Vec<String>, document: &OrderedDocument) -> Option<f64> {
     let mut index_document = document;
     for variable in variable_name {
         match index_document.get(variable) {
@@ -242,3 +798,18 @@ impl ResultWindow {
         None
     }
 }*/
+
+#[cfg(test)]
+mod tests {
+    use super::hour_of_week;
+
+    #[test]
+    fn hour_of_week_before_epoch() {
+        //the epoch is thursday 00:00
+        assert_eq!(hour_of_week(0), 72);
+        assert_eq!(hour_of_week(-1), 71);
+        assert_eq!(hour_of_week(-72 * 3600), 0);
+        assert_eq!(hour_of_week(-72 * 3600 - 1), 167);
+        assert_eq!(hour_of_week(-1 - (7 * 24 * 3600)), 71);
+    }
+}
@@ -0,0 +1,457 @@
+use bson::Bson;
+use bson::ordered::OrderedDocument;
+use regex::Regex;
+
+use error::TipupError;
+use result_window;
+
+use std::cmp::Ordering;
+
+//boolean expression over a measurement document, paths are dot separated and missing paths are
+//null. grammar:
+//  expression := and ('||' and)*
+//  and        := unary ('&&' unary)*
+//  unary      := '!' unary | '(' expression ')' | operand [operator operand]
+//  operator   := '==' | '!=' | '<' | '<=' | '>' | '>=' | '=~' | '!~'
+//  operand    := path | number | string | 'true' | 'false' | 'null'
+#[derive(Clone)]
+pub struct Rule {
+    expression: String,
+    root: Node,
+}
+
+impl Rule {
+    pub fn parse(expression: &str) -> Result<Rule, TipupError> {
+        let tokens = try!(tokenize(expression));
+        let mut parser = Parser {
+            tokens: tokens,
+            position: 0,
+        };
+
+        let root = try!(parser.parse_or());
+        if parser.position != parser.tokens.len() {
+            return Err(TipupError::from(format!("unexpected token {:?} in rule '{}'", parser.tokens[parser.position], expression)));
+        }
+
+        Ok(
+            Rule {
+                expression: expression.to_owned(),
+                root: root,
+            }
+        )
+    }
+
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    pub fn evaluate(&self, document: &OrderedDocument) -> bool {
+        evaluate(&self.root, document)
+    }
+
+    //dot separated paths referenced by the rule
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        collect_paths(&self.root, &mut paths);
+        paths
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Path(Vec<String>),
+    Number(f64),
+    Str(String),
+    Boolean(bool),
+    Null,
+    And,
+    Or,
+    Not,
+    LeftParen,
+    RightParen,
+    Operator(Operator),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Match,
+    NotMatch,
+}
+
+#[derive(Clone)]
+enum Operand {
+    Path(Vec<String>),
+    Literal(Bson),
+}
+
+#[derive(Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Exists(Operand),
+    Compare(Operand, Operator, Operand),
+    Match(Operand, Regex, bool),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, TipupError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = if i + 1 < chars.len() { Some(chars[i + 1]) } else { None };
+        match (c, next) {
+            (' ', _) | ('\t', _) | ('\n', _) | ('\r', _) => i += 1,
+            ('(', _) => { tokens.push(Token::LeftParen); i += 1; },
+            (')', _) => { tokens.push(Token::RightParen); i += 1; },
+            ('&', Some('&')) => { tokens.push(Token::And); i += 2; },
+            ('|', Some('|')) => { tokens.push(Token::Or); i += 2; },
+            ('=', Some('=')) => { tokens.push(Token::Operator(Operator::Equal)); i += 2; },
+            ('=', Some('~')) => { tokens.push(Token::Operator(Operator::Match)); i += 2; },
+            ('!', Some('=')) => { tokens.push(Token::Operator(Operator::NotEqual)); i += 2; },
+            ('!', Some('~')) => { tokens.push(Token::Operator(Operator::NotMatch)); i += 2; },
+            ('!', _) => { tokens.push(Token::Not); i += 1; },
+            ('<', Some('=')) => { tokens.push(Token::Operator(Operator::LessEqual)); i += 2; },
+            ('<', _) => { tokens.push(Token::Operator(Operator::Less)); i += 1; },
+            ('>', Some('=')) => { tokens.push(Token::Operator(Operator::GreaterEqual)); i += 2; },
+            ('>', _) => { tokens.push(Token::Operator(Operator::Greater)); i += 1; },
+            ('"', _) | ('\'', _) => {
+                //string literal, backslash only escapes the quote and itself so regexes pass through
+                let quote = c;
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    if i >= chars.len() {
+                        return Err(TipupError::from(format!("unterminated string in rule '{}'", expression)));
+                    }
+
+                    match chars[i] {
+                        '\\' if i + 1 < chars.len() && (chars[i + 1] == quote || chars[i + 1] == '\\') => {
+                            value.push(chars[i + 1]);
+                            i += 2;
+                        },
+                        x if x == quote => {
+                            i += 1;
+                            break;
+                        },
+                        x => {
+                            value.push(x);
+                            i += 1;
+                        },
+                    }
+                }
+
+                tokens.push(Token::Str(value));
+            },
+            (x, _) if x.is_digit(10) || (x == '-' && next.map_or(false, |y| y.is_digit(10))) => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_digit(10) || chars[i] == '.' || chars[i] == 'e' || chars[i] == 'E') {
+                    //an exponent may be signed, ex. 1e-5
+                    if (chars[i] == 'e' || chars[i] == 'E') && i + 1 < chars.len() && (chars[i + 1] == '+' || chars[i + 1] == '-') {
+                        i += 1;
+                    }
+
+                    i += 1;
+                }
+
+                let literal: String = chars[start..i].iter().cloned().collect();
+                match literal.parse::<f64>() {
+                    Ok(number) => tokens.push(Token::Number(number)),
+                    Err(_) => return Err(TipupError::from(format!("failed to parse number '{}' in rule '{}'", literal, expression))),
+                }
+            },
+            (x, _) if x.is_alphabetic() || x == '_' || x == '$' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$' || chars[i] == '.') {
+                    i += 1;
+                }
+
+                let word: String = chars[start..i].iter().cloned().collect();
+                match word.as_ref() {
+                    "true" => tokens.push(Token::Boolean(true)),
+                    "false" => tokens.push(Token::Boolean(false)),
+                    "null" => tokens.push(Token::Null),
+                    _ => {
+                        let path: Vec<String> = word.split('.').map(|x| x.to_owned()).collect();
+                        if path.iter().any(|x| x.len() == 0) {
+                            return Err(TipupError::from(format!("invalid path '{}' in rule '{}'", word, expression)));
+                        }
+
+                        tokens.push(Token::Path(path));
+                    },
+                }
+            },
+            (x, _) => return Err(TipupError::from(format!("unexpected character '{}' in rule '{}'", x, expression))),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Node, TipupError> {
+        let mut node = try!(self.parse_and());
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            let right = try!(self.parse_and());
+            node = Node::Or(Box::new(node), Box::new(right));
+        }
+
+        Ok(node)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, TipupError> {
+        let mut node = try!(self.parse_unary());
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            let right = try!(self.parse_unary());
+            node = Node::And(Box::new(node), Box::new(right));
+        }
+
+        Ok(node)
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, TipupError> {
+        match self.peek().cloned() {
+            Some(Token::Not) => {
+                self.position += 1;
+                let node = try!(self.parse_unary());
+                Ok(Node::Not(Box::new(node)))
+            },
+            Some(Token::LeftParen) => {
+                self.position += 1;
+                let node = try!(self.parse_or());
+                match self.next() {
+                    Some(Token::RightParen) => Ok(node),
+                    _ => Err(TipupError::from("expected ')' in rule")),
+                }
+            },
+            _ => self.parse_comparison(),
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Node, TipupError> {
+        let left = try!(self.parse_operand());
+        let operator = match self.peek() {
+            Some(&Token::Operator(operator)) => operator,
+            _ => return Ok(Node::Exists(left)),
+        };
+
+        self.position += 1;
+        match operator {
+            Operator::Match | Operator::NotMatch => {
+                let pattern = match self.next() {
+                    Some(Token::Str(pattern)) => pattern,
+                    _ => return Err(TipupError::from("expected string regular expression after '=~' or '!~' in rule")),
+                };
+
+                let regex = match Regex::new(&pattern) {
+                    Ok(regex) => regex,
+                    Err(e) => return Err(TipupError::from(format!("failed to compile regular expression '{}' in rule: {}", pattern, e))),
+                };
+
+                Ok(Node::Match(left, regex, operator == Operator::Match))
+            },
+            _ => {
+                let right = try!(self.parse_operand());
+                Ok(Node::Compare(left, operator, right))
+            },
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, TipupError> {
+        match self.next() {
+            Some(Token::Path(path)) => Ok(Operand::Path(path)),
+            Some(Token::Number(number)) => Ok(Operand::Literal(Bson::FloatingPoint(number))),
+            Some(Token::Str(value)) => Ok(Operand::Literal(Bson::String(value))),
+            Some(Token::Boolean(value)) => Ok(Operand::Literal(Bson::Boolean(value))),
+            Some(Token::Null) => Ok(Operand::Literal(Bson::Null)),
+            Some(token) => Err(TipupError::from(format!("expected operand in rule, found {:?}", token))),
+            None => Err(TipupError::from("unexpected end of rule")),
+        }
+    }
+}
+
+fn evaluate(node: &Node, document: &OrderedDocument) -> bool {
+    match *node {
+        Node::And(ref left, ref right) => evaluate(left, document) && evaluate(right, document),
+        Node::Or(ref left, ref right) => evaluate(left, document) || evaluate(right, document),
+        Node::Not(ref node) => !evaluate(node, document),
+        Node::Exists(ref operand) => match resolve(operand, document) {
+            Bson::Null => false,
+            Bson::Boolean(value) => value,
+            _ => true,
+        },
+        Node::Compare(ref left, operator, ref right) => {
+            let ordering = compare(&resolve(left, document), &resolve(right, document));
+            match (operator, ordering) {
+                (Operator::Equal, Some(Ordering::Equal)) => true,
+                (Operator::NotEqual, Some(Ordering::Equal)) => false,
+                (Operator::NotEqual, _) => true,
+                (Operator::Less, Some(Ordering::Less)) => true,
+                (Operator::LessEqual, Some(Ordering::Less)) | (Operator::LessEqual, Some(Ordering::Equal)) => true,
+                (Operator::Greater, Some(Ordering::Greater)) => true,
+                (Operator::GreaterEqual, Some(Ordering::Greater)) | (Operator::GreaterEqual, Some(Ordering::Equal)) => true,
+                _ => false,
+            }
+        },
+        Node::Match(ref operand, ref regex, positive) => match resolve(operand, document) {
+            Bson::String(ref value) => regex.is_match(value) == positive,
+            _ => !positive,
+        },
+    }
+}
+
+fn collect_paths(node: &Node, paths: &mut Vec<String>) {
+    match *node {
+        Node::And(ref left, ref right) | Node::Or(ref left, ref right) => {
+            collect_paths(left, paths);
+            collect_paths(right, paths);
+        },
+        Node::Not(ref node) => collect_paths(node, paths),
+        Node::Exists(ref operand) | Node::Match(ref operand, _, _) => add_path(operand, paths),
+        Node::Compare(ref left, _, ref right) => {
+            add_path(left, paths);
+            add_path(right, paths);
+        },
+    }
+}
+
+fn add_path(operand: &Operand, paths: &mut Vec<String>) {
+    if let Operand::Path(ref path) = *operand {
+        let path = path.join(".");
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+}
+
+fn resolve(operand: &Operand, document: &OrderedDocument) -> Bson {
+    match *operand {
+        Operand::Path(ref path) => match result_window::get_field(path, document) {
+            Some(value) => value.clone(),
+            None => Bson::Null,
+        },
+        Operand::Literal(ref value) => value.clone(),
+    }
+}
+
+fn compare(left: &Bson, right: &Bson) -> Option<Ordering> {
+    match (as_number(left), as_number(right)) {
+        (Some(left), Some(right)) => return left.partial_cmp(&right),
+        _ => (),
+    }
+
+    match (left, right) {
+        (&Bson::String(ref left), &Bson::String(ref right)) => Some(left.cmp(right)),
+        (&Bson::Boolean(left), &Bson::Boolean(right)) => Some(left.cmp(&right)),
+        (&Bson::Null, &Bson::Null) => Some(Ordering::Equal),
+        (&Bson::ObjectId(ref left), &Bson::ObjectId(ref right)) if left == right => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn as_number(value: &Bson) -> Option<f64> {
+    match *value {
+        Bson::FloatingPoint(f) => Some(f),
+        Bson::I32(i) => Some(i as f64),
+        Bson::I64(i) => Some(i as f64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::Bson;
+    use bson::ordered::OrderedDocument;
+
+    use super::Rule;
+
+    fn measurement() -> OrderedDocument {
+        let http = doc!("status_code" => 404i64, "latency" => 0.00002);
+        doc!(
+            "http" => (Bson::Document(http)),
+            "measurement_domain" => "example.gov",
+            "remaining_attempts" => 0i32,
+            "blocked" => false
+        )
+    }
+
+    fn evaluate(expression: &str) -> bool {
+        Rule::parse(expression).unwrap().evaluate(&measurement())
+    }
+
+    #[test]
+    fn parse_valid() {
+        let rule = Rule::parse("http.status_code >= 400 && (measurement_domain =~ \".*\\.gov$\" || !blocked)").unwrap();
+        assert_eq!(rule.paths(), vec!["http.status_code", "measurement_domain", "blocked"]);
+    }
+
+    #[test]
+    fn parse_signed_exponent() {
+        assert!(evaluate("http.latency < 1e-4"));
+        assert!(evaluate("http.latency > 1E-5"));
+        assert!(evaluate("http.status_code < 1e+3"));
+        assert!(evaluate("http.status_code == 4.04e2"));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for expression in &["", "http.status_code >=", "(blocked", "blocked)", "a..b", "x == 'open",
+                "measurement_domain =~ 5", "measurement_domain =~ \"(\"", "1e-", "a # b", "&& blocked"] {
+            assert!(Rule::parse(expression).is_err(), "'{}' should not parse", expression);
+        }
+    }
+
+    #[test]
+    fn evaluate_comparisons() {
+        assert!(evaluate("http.status_code == 404"));
+        assert!(evaluate("http.status_code != 200"));
+        assert!(evaluate("remaining_attempts <= 0 && remaining_attempts >= 0"));
+        assert!(evaluate("measurement_domain == 'example.gov'"));
+        assert!(evaluate("measurement_domain =~ \".*\\.gov$\""));
+        assert!(evaluate("measurement_domain !~ \"\\.com$\""));
+        assert!(!evaluate("http.status_code > 404"));
+        assert!(!evaluate("measurement_domain == 404"));
+    }
+
+    #[test]
+    fn evaluate_missing_paths() {
+        assert!(evaluate("http"));
+        assert!(!evaluate("blocked"));
+        assert!(!evaluate("http.body"));
+        assert!(evaluate("http.body == null"));
+        assert!(evaluate("http.body != 404"));
+        assert!(!evaluate("http.body =~ \".*\""));
+    }
+
+    #[test]
+    fn evaluate_precedence() {
+        assert!(evaluate("blocked && blocked || http"));
+        assert!(!evaluate("blocked && (blocked || http)"));
+        assert!(evaluate("!blocked && !(http.status_code < 400)"));
+    }
+}
@@ -0,0 +1,154 @@
+use bson::Bson;
+use bson::ordered::OrderedDocument;
+use chan::Sender;
+use mongodb::coll::options::{CursorType, FindOneAndUpdateOptions, FindOptions};
+use mongodb::db::{Database, ThreadedDatabase};
+
+use error::TipupError;
+
+use std::thread;
+use std::time::Duration;
+
+const NAMESPACE: &'static str = "proddle.measurements";
+
+//events sent by a running measurement stream
+pub enum StreamEvent {
+    //an inserted measurement and the oplog position to resume after
+    Measurement(OrderedDocument, Bson),
+    //tailing stopped, ingestion must fall back to polling
+    Failed(TipupError),
+}
+
+//tails the replica set oplog for inserts into proddle.measurements, the oplog timestamp of the
+//last measurement is the resume token persisted in 'stream_checkpoints'
+pub struct MeasurementStream {
+    oplog_db: Database,
+    resume_token: Bson,
+}
+
+impl MeasurementStream {
+    //fails when there is no oplog to tail, ex. mongodb is not running as a replica set
+    pub fn open(db: &Database, oplog_db: Database) -> Result<MeasurementStream, TipupError> {
+        let resume_token = match try!(load_resume_token(db)) {
+            Some(resume_token) => resume_token,
+            None => {
+                //without a token start at the end of the oplog, earlier measurements are polled
+                let sort_document = doc!("$natural" => (-1));
+                let find_options = Some(FindOptions {
+                    allow_partial_results: false,
+                    no_cursor_timeout: false,
+                    oplog_replay: false,
+                    skip: None,
+                    limit: None,
+                    cursor_type: CursorType::NonTailable,
+                    batch_size: None,
+                    comment: None,
+                    max_time_ms: None,
+                    modifiers: None,
+                    projection: None,
+                    sort: Some(sort_document),
+                    read_preference: None,
+                });
+
+                match try!(oplog_db.collection("oplog.rs").find_one(None, find_options)) {
+                    Some(document) => match document.get("ts") {
+                        Some(&Bson::TimeStamp(ts)) => Bson::TimeStamp(ts),
+                        _ => return Err(TipupError::from("failed to parse 'ts' from the latest oplog entry")),
+                    },
+                    None => return Err(TipupError::from("no oplog found, mongodb must run as a replica set to stream measurements")),
+                }
+            },
+        };
+
+        Ok(
+            MeasurementStream {
+                oplog_db: oplog_db,
+                resume_token: resume_token,
+            }
+        )
+    }
+
+    //sends measurements until tailing fails, then sends the failure and returns
+    pub fn run(mut self, stream_tx: Sender<StreamEvent>) {
+        if let Err(e) = self.tail(&stream_tx) {
+            stream_tx.send(StreamEvent::Failed(e));
+        }
+    }
+
+    fn tail(&mut self, stream_tx: &Sender<StreamEvent>) -> Result<(), TipupError> {
+        loop {
+            let ts_gt = doc!("$gt" => (self.resume_token.clone()));
+            let search_document = Some(doc!(
+                "ts" => ts_gt,
+                "ns" => NAMESPACE,
+                "op" => "i"
+            ));
+
+            let find_options = Some(FindOptions {
+                allow_partial_results: false,
+                no_cursor_timeout: false,
+                oplog_replay: true,
+                skip: None,
+                limit: None,
+                cursor_type: CursorType::TailableAwait,
+                batch_size: None,
+                comment: None,
+                max_time_ms: None,
+                modifiers: None,
+                projection: None,
+                sort: None,
+                read_preference: None,
+            });
+
+            //the cursor ends when an await times out without new entries, so it is reopened
+            let mut count = 0;
+            let cursor = try!(self.oplog_db.collection("oplog.rs").find(search_document, find_options));
+            for entry in cursor {
+                let entry = try!(entry);
+                let ts = match entry.get("ts") {
+                    Some(&Bson::TimeStamp(ts)) => Bson::TimeStamp(ts),
+                    _ => return Err(TipupError::from("failed to parse 'ts' from oplog entry")),
+                };
+
+                match entry.get("o") {
+                    Some(&Bson::Document(ref document)) => stream_tx.send(StreamEvent::Measurement(document.clone(), ts.clone())),
+                    _ => return Err(TipupError::from(format!("failed to parse 'o' from oplog entry at {}", ts))),
+                }
+
+                self.resume_token = ts;
+                count += 1;
+            }
+
+            if count == 0 {
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+fn load_resume_token(db: &Database) -> Result<Option<Bson>, TipupError> {
+    let search_document = Some(doc!("_id" => NAMESPACE));
+    match try!(db.collection("stream_checkpoints").find_one(search_document, None)) {
+        Some(document) => match document.get("resume_token") {
+            Some(&Bson::TimeStamp(ts)) => Ok(Some(Bson::TimeStamp(ts))),
+            _ => Err(TipupError::from("failed to parse 'resume_token' in stream_checkpoints")),
+        },
+        None => Ok(None),
+    }
+}
+
+pub fn save_resume_token(db: &Database, resume_token: &Bson) -> Result<(), TipupError> {
+    let search_document = doc!("_id" => NAMESPACE);
+    let checkpoint_document = doc!("_id" => NAMESPACE, "resume_token" => (resume_token.clone()));
+    let update_options = Some(FindOneAndUpdateOptions {
+        return_document: None,
+        max_time_ms: None,
+        projection: None,
+        sort: None,
+        upsert: Some(true),
+        write_concern: None,
+    });
+
+    try!(db.collection("stream_checkpoints").find_one_and_replace(search_document, checkpoint_document, update_options));
+    Ok(())
+}